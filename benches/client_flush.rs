@@ -0,0 +1,161 @@
+//! Compares flushing small request bodies inline from `call` against flushing
+//! them on a spawned task.
+
+#[macro_use]
+extern crate criterion;
+extern crate bytes;
+#[macro_use]
+extern crate futures;
+extern crate h2;
+extern crate http;
+extern crate tokio;
+extern crate tower;
+extern crate tower_h2;
+extern crate tower_service;
+
+use bytes::{Bytes, IntoBuf};
+use criterion::Criterion;
+use futures::{future, Async, Future, Poll, Stream};
+use http::{Request, Response};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{Runtime, TaskExecutor};
+use tower::MakeService;
+use tower_h2::client::{Connect, Connection};
+use tower_h2::{Body, NoBody, RecvBody, Server};
+use tower_service::Service;
+
+use std::io;
+use std::net::SocketAddr;
+
+/// A request body that is already buffered.
+struct Unary(Option<Bytes>);
+
+impl Body for Unary {
+    type Data = <Bytes as IntoBuf>::Buf;
+    type Error = h2::Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Ok(Async::Ready(self.0.take().map(IntoBuf::into_buf)))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(Async::Ready(None))
+    }
+}
+
+struct Svc;
+
+impl Service<Request<RecvBody>> for Svc {
+    type Response = Response<NoBody>;
+    type Error = h2::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: Request<RecvBody>) -> Self::Future {
+        future::ok(Response::new(NoBody))
+    }
+}
+
+struct NewSvc;
+
+impl Service<()> for NewSvc {
+    type Response = Svc;
+    type Error = io::Error;
+    type Future = future::FutureResult<Svc, io::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        future::ok(Svc)
+    }
+}
+
+struct Conn(SocketAddr);
+
+impl Service<()> for Conn {
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = tokio::net::tcp::ConnectFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        TcpStream::connect(&self.0)
+    }
+}
+
+/// Serves `Svc` on a loopback port, returning its address.
+fn serve(rt: &mut Runtime) -> SocketAddr {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+
+    let h2 = Server::new(NewSvc, Default::default(), rt.executor());
+    let serve = listener
+        .incoming()
+        .fold(h2, |mut h2, sock| {
+            sock.set_nodelay(true)?;
+            tokio::spawn(h2.serve(sock).map_err(|_| ()));
+            Ok::<_, io::Error>(h2)
+        })
+        .map(|_| ())
+        .map_err(|_| ());
+    rt.spawn(serve);
+
+    addr
+}
+
+fn connect(
+    rt: &mut Runtime,
+    addr: SocketAddr,
+    inline: bool,
+) -> Connection<TcpStream, TaskExecutor, Unary> {
+    let mut connect = Connect::new(Conn(addr), Default::default(), rt.executor());
+    connect.set_inline_flush(inline);
+    rt.block_on(connect.make_service(())).expect("connect")
+}
+
+fn unary(c: &mut Criterion, name: &str, inline: bool) {
+    let mut rt = Runtime::new().expect("runtime");
+    let addr = serve(&mut rt);
+    let client = connect(&mut rt, addr, inline);
+
+    c.bench_function(name, move |b| {
+        b.iter(|| {
+            let request = Request::builder()
+                .method("POST")
+                .uri("http://127.0.0.1/")
+                .body(Unary(Some(Bytes::from_static(b"hello world"))))
+                .unwrap();
+
+            let mut client = Some(client.clone());
+            let call = future::poll_fn(move || {
+                try_ready!(client.as_mut().unwrap().poll_ready());
+                Ok(Async::Ready(client.take().unwrap()))
+            })
+            .and_then(move |mut client| client.call(request));
+            rt.block_on(call).expect("response")
+        })
+    });
+}
+
+fn spawned_flush(c: &mut Criterion) {
+    unary(c, "unary request body, spawned flush", false);
+}
+
+fn inline_flush(c: &mut Criterion) {
+    unary(c, "unary request body, inline flush", true);
+}
+
+criterion_group!(benches, spawned_flush, inline_flush);
+criterion_main!(benches);
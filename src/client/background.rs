@@ -1,10 +1,19 @@
+use super::{Sniff, Status};
 use buf::SendBuf;
 use flush::Flush;
+use keepalive::Pinger;
+use timer::{self, Timer};
 use Body;
 
-use futures::{Future, Poll};
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+use h2;
 use h2::client::Connection;
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Task that performs background tasks for a client.
 ///
@@ -21,8 +30,31 @@ enum Task<T, S>
 where
     S: Body,
 {
-    Connection(Connection<T, SendBuf<S::Data>>),
-    Flush(Flush<S>),
+    Connection {
+        connection: Connection<Sniff<T>, SendBuf<S::Data>>,
+        keepalive: Option<Pinger>,
+        idle: Option<Idle>,
+        status: Arc<Status>,
+    },
+    Flush {
+        flush: Flush<S>,
+        /// Fires if the response is abandoned before the body has been sent.
+        canceled: Option<oneshot::Receiver<()>>,
+    },
+}
+
+/// Stops a request's body flush task when dropped, unless it is disarmed.
+///
+/// Held by a `ResponseFuture`, and then by the `RecvBody` of its response
+/// until the response has been received.
+#[derive(Debug)]
+pub(crate) struct CancelFlush(Option<oneshot::Sender<()>>);
+
+/// Closes a connection once it has had no requests in flight for a timeout.
+pub(crate) struct Idle {
+    timeout: Duration,
+    timer: Timer,
+    delay: Option<Delay>,
 }
 
 // ===== impl Background =====
@@ -31,13 +63,26 @@ impl<T, S> Background<T, S>
 where
     S: Body,
 {
-    pub(crate) fn connection(connection: Connection<T, SendBuf<S::Data>>) -> Self {
-        let task = Task::Connection(connection);
+    pub(crate) fn connection(
+        connection: Connection<Sniff<T>, SendBuf<S::Data>>,
+        keepalive: Option<Pinger>,
+        idle: Option<Idle>,
+        status: Arc<Status>,
+    ) -> Self {
+        let task = Task::Connection {
+            connection,
+            keepalive,
+            idle,
+            status,
+        };
         Background { task }
     }
 
-    pub(crate) fn flush(flush: Flush<S>) -> Self {
-        let task = Task::Flush(flush);
+    pub(crate) fn flush(flush: Flush<S>, canceled: oneshot::Receiver<()>) -> Self {
+        let task = Task::Flush {
+            flush,
+            canceled: Some(canceled),
+        };
         Background { task }
     }
 }
@@ -56,10 +101,115 @@ where
         use self::Task::*;
 
         match self.task {
-            Connection(ref mut f) => f.poll().map_err(|err| {
-                warn!("error driving HTTP/2 client connection: {:?}", err);
-            }),
-            Flush(ref mut f) => f.poll(),
+            Connection {
+                ref mut connection,
+                ref mut keepalive,
+                ref mut idle,
+                ref status,
+            } => {
+                if keepalive.as_mut().map_or(false, Pinger::poll_expired) {
+                    // The peer is unresponsive, so drop the connection rather
+                    // than waiting on it any longer.
+                    debug!("keepalive PING timed out; closing connection");
+                    status.set_keepalive_expired();
+                    status.set_closed();
+                    return Ok(Async::Ready(()));
+                }
+
+                if idle.as_mut().map_or(false, |idle| idle.poll_expired(status)) {
+                    debug!("idle timeout elapsed; closing connection");
+                    status.set_closed();
+                    return Ok(Async::Ready(()));
+                }
+
+                let poll = connection.poll().map_err(|err| {
+                    warn!("error driving HTTP/2 client connection: {:?}", err);
+                });
+
+                match poll {
+                    Ok(Async::NotReady) => {}
+                    _ => status.set_closed(),
+                }
+
+                poll
+            }
+            Flush {
+                ref mut flush,
+                ref mut canceled,
+            } => {
+                let is_canceled = match canceled.as_mut().map(Future::poll) {
+                    Some(Ok(Async::Ready(()))) => true,
+                    Some(Ok(Async::NotReady)) | None => false,
+                    // The response was received, so the rest of the body
+                    // should still be sent.
+                    Some(Err(oneshot::Canceled)) => {
+                        *canceled = None;
+                        false
+                    }
+                };
+
+                if is_canceled {
+                    debug!("response abandoned; resetting stream");
+                    flush.send_reset(h2::Reason::CANCEL);
+                    return Ok(Async::Ready(()));
+                }
+
+                flush.poll()
+            }
         }
     }
 }
+
+// ===== impl CancelFlush =====
+
+impl CancelFlush {
+    pub fn new() -> (Self, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        (CancelFlush(Some(tx)), rx)
+    }
+
+    /// Let the flush task finish sending the body.
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelFlush {
+    fn drop(&mut self) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+// ===== impl Idle =====
+
+impl Idle {
+    pub fn new(timeout: Duration, timer: Timer) -> Self {
+        Idle {
+            timeout,
+            timer,
+            delay: None,
+        }
+    }
+
+    /// Returns `true` once no requests have been in flight for the timeout.
+    fn poll_expired(&mut self, status: &Status) -> bool {
+        let emptied = status.poll_emptied();
+
+        if status.in_flight() > 0 {
+            self.delay = None;
+            return false;
+        }
+
+        // Restart the timeout whenever the connection becomes idle again.
+        if emptied {
+            self.delay = None;
+        }
+
+        let timer = &self.timer;
+        let timeout = self.timeout;
+        let delay = self.delay.get_or_insert_with(|| timer.delay(timeout));
+        timer::poll_elapsed(delay)
+    }
+}
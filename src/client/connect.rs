@@ -1,5 +1,5 @@
 use super::{Background, Connection, Handshake, HandshakeError};
-use Body;
+use {Body, KeepAlive, ResetReasons, Timer};
 
 use tower::MakeConnection;
 use tower_service::Service;
@@ -11,6 +11,8 @@ use h2;
 use std::error::Error;
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Establishes an H2 client connection.
 ///
@@ -23,6 +25,9 @@ pub struct Connect<A, C, E, S> {
     /// HTTP/2.0 client configuration
     builder: h2::client::Builder,
 
+    /// Configuration that is not handled by h2 itself.
+    config: Config,
+
     /// Used to spawn connection management tasks and tasks to flush send
     /// body streams.
     executor: E,
@@ -48,6 +53,20 @@ where
 
     /// HTTP/2.0 client configuration
     builder: h2::client::Builder,
+
+    /// Configuration that is not handled by h2 itself.
+    config: Config,
+}
+
+/// Client connection configuration that is not handled by h2 itself.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Config {
+    pub keepalive: Option<KeepAlive>,
+    pub idle_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub reset_reasons: Arc<ResetReasons>,
+    pub inline_flush: bool,
+    pub timer: Timer,
 }
 
 /// Represents the state of a `ConnectFuture`
@@ -92,9 +111,75 @@ where
             inner,
             executor,
             builder,
+            config: Config::default(),
             _p: PhantomData,
         }
     }
+
+    /// Sets the `Timer` used to drive client connection timeouts.
+    pub fn set_timer(&mut self, timer: Timer) {
+        self.config.timer = timer;
+    }
+
+    /// Sets the PING keepalive used by new connections.
+    ///
+    /// If the peer fails to acknowledge a keepalive PING in time, the
+    /// connection is closed. Its in-flight requests, and any further calls to
+    /// `poll_ready`, then fail with an error for which
+    /// `Error::is_keepalive_timeout` returns `true`. By default, no keepalive
+    /// PINGs are sent.
+    pub fn set_keepalive(&mut self, keepalive: Option<KeepAlive>) {
+        self.config.keepalive = keepalive;
+    }
+
+    /// Sets how long a new connection may have no requests in flight before
+    /// it is closed.
+    ///
+    /// A request is in flight until its response body has been received, or
+    /// the response is dropped. By default, idle connections are kept open
+    /// indefinitely.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.config.idle_timeout = timeout;
+    }
+
+    /// Sets how long requests on new connections wait for a response.
+    ///
+    /// If the response HEADERS are not received in time, the request's stream
+    /// is reset with CANCEL and the request fails with an error for which
+    /// `Error::is_timeout` returns `true`. A `RequestTimeout` in a request's
+    /// extensions overrides this. By default, requests wait indefinitely.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.config.request_timeout = timeout;
+    }
+
+    /// Sets how request body errors are classified into the reason their
+    /// streams are reset with.
+    pub fn set_reset_reasons(&mut self, reasons: ResetReasons) {
+        self.config.reset_reasons = Arc::new(reasons);
+    }
+
+    /// Sets whether new connections try to send request bodies from `call`.
+    ///
+    /// When enabled, `call` polls the request body once, on the calling task,
+    /// and sends its first chunk, or its trailers, if they are ready. A task
+    /// to flush the rest of the body is only spawned if the body has not
+    /// ended once that is sent, so small, already-buffered bodies such as
+    /// unary gRPC messages are sent without spawning a task. By default, every
+    /// request body is flushed by a spawned task.
+    ///
+    /// Because the body and the stream are polled from `call`, `call` must
+    /// then be invoked from within a task, such as from a future's `poll`.
+    /// Calling it outside of a task panics.
+    pub fn set_inline_flush(&mut self, enabled: bool) {
+        self.config.inline_flush = enabled;
+    }
+}
+
+impl<A, C, E, S> Connect<A, C, E, S> {
+    /// Returns the `Timer` used to drive client connection timeouts.
+    pub(crate) fn timer(&self) -> &Timer {
+        &self.config.timer
+    }
 }
 
 impl<A, C, E, S> Service<A> for Connect<A, C, E, S>
@@ -116,10 +201,12 @@ where
     fn call(&mut self, target: A) -> Self::Future {
         let state = State::Connect(self.inner.make_connection(target));
         let builder = self.builder.clone();
+        let config = self.config.clone();
 
         ConnectFuture {
             state,
             builder,
+            config,
             executor: Some(self.executor.clone()),
         }
     }
@@ -152,7 +239,7 @@ where
             };
 
             let executor = self.executor.take().expect("double poll");
-            let handshake = Handshake::new(io, executor, &self.builder);
+            let handshake = Handshake::new(io, executor, &self.builder, &self.config);
 
             self.state = State::Handshake(handshake);
         }
@@ -1,7 +1,10 @@
-use super::Background;
+use super::background::{CancelFlush, Idle};
+use super::{Background, Config, ConnectionState, GoAway, InFlight, Sniff, Status};
 use buf::SendBuf;
 use flush::Flush;
-use {Body, RecvBody};
+use keepalive::Pinger;
+use timer::{self, Timer};
+use {Body, BodyError, RecvBody, ResetReasons};
 
 use futures::future::Executor;
 use futures::{Async, Future, Poll};
@@ -9,10 +12,13 @@ use h2;
 use h2::client::{self, Builder, SendRequest};
 use http::{self, Request, Response};
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
 use tower_service::Service;
 
 use std::marker::PhantomData;
-use std::{error, fmt};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{error, fmt, mem};
 
 /// Exposes a request/response API on an h2 client connection..
 pub struct Connection<T, E, S>
@@ -21,6 +27,11 @@ where
 {
     client: SendRequest<SendBuf<S::Data>>,
     executor: E,
+    status: Arc<Status>,
+    request_timeout: Option<Duration>,
+    reset_reasons: Arc<ResetReasons>,
+    inline_flush: bool,
+    timer: Timer,
     _p: PhantomData<(T, S)>,
 }
 
@@ -29,8 +40,10 @@ pub struct Handshake<T, E, S>
 where
     S: Body,
 {
-    inner: h2::client::Handshake<T, SendBuf<S::Data>>,
+    inner: h2::client::Handshake<Sniff<T>, SendBuf<S::Data>>,
     executor: E,
+    config: Config,
+    status: Arc<Status>,
 }
 
 /// Drives the sending of a request (and its body) until a response is received (i.e. the
@@ -38,23 +51,89 @@ where
 ///
 /// This is necessary because, for instance, the remote server may not respond until the
 /// request body is fully sent.
+///
+/// Dropping the `ResponseFuture`, or the response's `RecvBody` before it has been received,
+/// resets the stream with CANCEL and stops sending the request body.
 pub struct ResponseFuture {
     inner: Inner,
+    status: Arc<Status>,
+    in_flight: Option<InFlight>,
+    timeout: Option<Delay>,
+
+    /// Stops the task flushing the request body if the response is
+    /// abandoned.
+    cancel_flush: Option<CancelFlush>,
+
+    /// Receives the error the request body fails with, if any.
+    body_error: Option<Arc<Mutex<Option<BodyError>>>>,
 }
 
+/// Overrides the request timeout set by `Connect::set_request_timeout` for a
+/// single request.
+///
+/// The override applies when a `RequestTimeout` is inserted into the
+/// request's extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestTimeout(Option<Duration>);
+
 /// ResponseFuture inner
 enum Inner {
     /// Inner response future
     Inner(client::ResponseFuture),
 
     /// Failed to send the request
-    Error(Option<Error>),
+    Error(Error),
+
+    /// The future has failed, so polling it again fails with another error of
+    /// the same kind.
+    Failed(ErrorKind),
 }
 
 /// Errors produced by client `Connection` calls.
 #[derive(Debug)]
 pub struct Error {
-    kind: Kind,
+    kind: ErrorKind,
+    source: Option<h2::Error>,
+    body: Option<BodyError>,
+}
+
+/// The category of a client `Error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The connection closed before the request completed.
+    ///
+    /// If the server sent a GOAWAY, requests on streams after its last stream
+    /// ID were not processed.
+    Closed { go_away: Option<GoAway> },
+
+    /// The server reset the request's stream.
+    Reset(h2::Reason),
+
+    /// A flow control limit was violated, so the connection, or the request's
+    /// stream, was closed.
+    ///
+    /// This is usually because the server violated the client's limits, but
+    /// h2 does not report which peer detected the violation.
+    FlowControl,
+
+    /// No response was received before the request timed out.
+    Timeout,
+
+    /// The connection was closed because the server failed to acknowledge a
+    /// keepalive PING in time.
+    KeepAliveTimeout,
+
+    /// The request body failed while it was being sent.
+    Body,
+
+    /// An I/O error occurred on the connection.
+    Io,
+
+    /// Any other HTTP/2 protocol error.
+    Protocol,
+
+    /// A background task could not be spawned.
+    Spawn,
 }
 
 /// Error produced when performing an HTTP/2.0 handshake.
@@ -67,12 +146,6 @@ pub enum HandshakeError {
     Execute,
 }
 
-#[derive(Debug)]
-enum Kind {
-    Inner(h2::Error),
-    Spawn,
-}
-
 // ===== impl Connection =====
 
 impl<T, E, S> Connection<T, E, S>
@@ -84,19 +157,65 @@ where
     T: AsyncRead + AsyncWrite,
 {
     /// Builds Connection on an H2 client connection.
-    pub(crate) fn new(client: SendRequest<SendBuf<S::Data>>, executor: E) -> Self {
+    pub(crate) fn new(
+        client: SendRequest<SendBuf<S::Data>>,
+        executor: E,
+        status: Arc<Status>,
+        config: &Config,
+    ) -> Self {
         let _p = PhantomData;
 
         Connection {
             client,
             executor,
+            status,
+            request_timeout: config.request_timeout,
+            reset_reasons: config.reset_reasons.clone(),
+            inline_flush: config.inline_flush,
+            timer: config.timer.clone(),
             _p,
         }
     }
 
     /// Perform the HTTP/2.0 handshake, yielding a `Connection` on completion.
     pub fn handshake(io: T, executor: E) -> Handshake<T, E, S> {
-        Handshake::new(io, executor, &Builder::default())
+        Handshake::new(io, executor, &Builder::default(), &Config::default())
+    }
+}
+
+impl<T, E, S> Connection<T, E, S>
+where
+    S: Body,
+{
+    /// Returns the state of the connection, as last observed by the task
+    /// driving it.
+    ///
+    /// Once the server has sent a GOAWAY, the connection is `Draining` and
+    /// new requests should be routed elsewhere.
+    pub fn state(&self) -> ConnectionState {
+        self.status.state()
+    }
+
+    /// Returns the most recent GOAWAY received from the server, if any.
+    pub fn go_away(&self) -> Option<GoAway> {
+        self.status.go_away()
+    }
+
+    /// Returns `true` if another request may be sent without exceeding the
+    /// server's SETTINGS_MAX_CONCURRENT_STREAMS.
+    pub(crate) fn has_capacity(&self) -> bool {
+        self.status.has_capacity()
+    }
+
+    /// Counts a request as in flight on this connection until the returned
+    /// guard is dropped, before the request has been sent.
+    pub(crate) fn reserve(&self) -> InFlight {
+        InFlight::new(self.status.clone())
+    }
+
+    /// Returns `true` if `other` is a handle to the same connection.
+    pub(crate) fn is_same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.status, &other.status)
     }
 }
 
@@ -109,6 +228,11 @@ where
         Connection {
             client: self.client.clone(),
             executor: self.executor.clone(),
+            status: self.status.clone(),
+            request_timeout: self.request_timeout,
+            reset_reasons: self.reset_reasons.clone(),
+            inline_flush: self.inline_flush,
+            timer: self.timer.clone(),
             _p: PhantomData,
         }
     }
@@ -127,12 +251,25 @@ where
     type Future = ResponseFuture;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        self.client.poll_ready().map_err(Into::into)
+        if self.status.keepalive_expired() {
+            return Err(Error::new(ErrorKind::KeepAliveTimeout, None));
+        }
+
+        let status = &self.status;
+        self.client
+            .poll_ready()
+            .map_err(|e| Error::connection(e, status))
     }
 
     fn call(&mut self, request: Request<S>) -> Self::Future {
         trace!("request: {} {}", request.method(), request.uri());
 
+        let timeout = request
+            .extensions()
+            .get::<RequestTimeout>()
+            .map_or(self.request_timeout, RequestTimeout::get)
+            .map(|timeout| self.timer.delay(timeout));
+
         // Split the request from the body
         let (parts, body) = request.into_parts();
         let request = http::Request::from_parts(parts, ());
@@ -147,27 +284,62 @@ where
         let (response, send_body) = match res {
             Ok(success) => success,
             Err(e) => {
-                let e = Error {
-                    kind: Kind::Inner(e),
-                };
-                let inner = Inner::Error(Some(e));
-                return ResponseFuture { inner };
+                let e = Error::connection(e, &self.status);
+                let inner = Inner::Error(e);
+                let status = self.status.clone();
+                return ResponseFuture::failed(inner, status);
             }
         };
 
+        // Without a body, the response holds the only handles to the stream,
+        // so dropping them resets it. Otherwise, the flush task must be told
+        // to reset it.
+        let mut cancel_flush = None;
+        let mut body_error = None;
         if !eos {
-            let flush = Flush::new(body, send_body);
-            let res = self.executor.execute(Background::flush(flush));
+            let mut flush = Flush::new(body, send_body, self.reset_reasons.clone());
+            let slot = Arc::new(Mutex::new(None));
+            flush.set_body_error(slot.clone());
+            body_error = Some(slot);
+
+            // Send the body's first chunk now, if it is ready. A task only
+            // has to be spawned if the body did not end with that chunk. Only
+            // one chunk is sent, to bound the work done by `call`. Polling the
+            // body and stream requires `call` to be invoked on a task, as
+            // documented on `Connect::set_inline_flush`.
+            let flushed = self.inline_flush
+                && match flush.poll_send() {
+                    Ok(Async::Ready(done)) => done,
+                    Ok(Async::NotReady) => false,
+                    Err(e) => {
+                        // The stream has been reset, so the response future
+                        // will fail.
+                        debug!("error flushing request body: {:?}", e);
+                        true
+                    }
+                };
 
-            if let Err(_) = res {
-                let e = Error { kind: Kind::Spawn };
-                let inner = Inner::Error(Some(e));
-                return ResponseFuture { inner };
+            if !flushed {
+                let (cancel, canceled) = CancelFlush::new();
+                cancel_flush = Some(cancel);
+                let res = self.executor.execute(Background::flush(flush, canceled));
+
+                if let Err(_) = res {
+                    let e = Error::new(ErrorKind::Spawn, None);
+                    let inner = Inner::Error(e);
+                    let status = self.status.clone();
+                    return ResponseFuture::failed(inner, status);
+                }
             }
         }
 
         ResponseFuture {
             inner: Inner::Inner(response),
+            status: self.status.clone(),
+            in_flight: Some(InFlight::new(self.status.clone())),
+            timeout,
+            cancel_flush,
+            body_error,
         }
     }
 }
@@ -181,23 +353,75 @@ impl Future for ResponseFuture {
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         use self::Inner::*;
 
-        match self.inner {
+        let response = match self.inner {
             Inner(ref mut fut) => {
-                let response = try_ready!(fut.poll());
-
-                let (parts, body) = response.into_parts();
-                let body = RecvBody::new(body);
-
-                Ok(Response::from_parts(parts, body).into())
+                let id = fut.stream_id();
+                let status = &self.status;
+                let body_error = &self.body_error;
+                fut.poll().map_err(|e| {
+                    let body_error = body_error
+                        .as_ref()
+                        .and_then(|slot| slot.lock().unwrap().take());
+                    ::client::Error::stream(e, id, status, body_error)
+                })?
             }
-            Error(ref mut e) => {
-                return Err(e.take().unwrap());
+            Error(_) | Failed(_) => return Err(self.take_error()),
+        };
+
+        let response = match response {
+            Async::Ready(response) => response,
+            Async::NotReady => {
+                if self.timeout.as_mut().map_or(false, timer::poll_elapsed) {
+                    return Err(self.time_out());
+                }
+                return Ok(Async::NotReady);
             }
-        }
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = RecvBody::client(body, self.in_flight.take(), self.cancel_flush.take());
+
+        Ok(Response::from_parts(parts, body).into())
     }
 }
 
 impl ResponseFuture {
+    fn failed(inner: Inner, status: Arc<Status>) -> Self {
+        ResponseFuture {
+            inner,
+            status,
+            in_flight: None,
+            timeout: None,
+            cancel_flush: None,
+            body_error: None,
+        }
+    }
+
+    /// Abandon the request, releasing its stream.
+    fn time_out(&mut self) -> Error {
+        debug!("request timed out; resetting stream");
+
+        self.inner = Inner::Failed(ErrorKind::Timeout);
+        self.in_flight = None;
+        self.cancel_flush = None;
+
+        Error::new(ErrorKind::Timeout, None)
+    }
+
+    /// Returns the error this future failed with, leaving it failed.
+    fn take_error(&mut self) -> Error {
+        let kind = match self.inner {
+            Inner::Error(ref e) => e.kind(),
+            Inner::Failed(kind) => kind,
+            Inner::Inner(_) => unreachable!(),
+        };
+
+        match mem::replace(&mut self.inner, Inner::Failed(kind)) {
+            Inner::Error(e) => e,
+            _ => Error::new(kind, None),
+        }
+    }
+
     /// Returns the stream ID of the response stream, or `None` if this future
     /// does not correspond to a stream.
     pub fn stream_id(&self) -> Option<h2::StreamId> {
@@ -208,6 +432,25 @@ impl ResponseFuture {
     }
 }
 
+// ===== impl RequestTimeout =====
+
+impl RequestTimeout {
+    /// Fail the request if no response is received within `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        RequestTimeout(Some(timeout))
+    }
+
+    /// Wait for the response indefinitely.
+    pub fn none() -> Self {
+        RequestTimeout(None)
+    }
+
+    /// Returns the timeout, if any.
+    pub fn get(&self) -> Option<Duration> {
+        self.0
+    }
+}
+
 // ===== impl Handshake =====
 
 impl<T, E, S> Handshake<T, E, S>
@@ -217,10 +460,17 @@ where
     S::Data: 'static,
 {
     /// Start an HTTP/2.0 handshake with the provided builder
-    pub(crate) fn new(io: T, executor: E, builder: &Builder) -> Self {
-        let inner = builder.handshake(io);
+    pub(crate) fn new(io: T, executor: E, builder: &Builder, config: &Config) -> Self {
+        let status = Status::new();
+        let inner = builder.handshake(Sniff::new(io, status.clone()));
+        let config = config.clone();
 
-        Handshake { inner, executor }
+        Handshake {
+            inner,
+            executor,
+            config,
+            status,
+        }
     }
 }
 
@@ -236,17 +486,29 @@ where
     type Error = HandshakeError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let (client, connection) = try_ready!(self.inner.poll());
+        let (client, mut connection) = try_ready!(self.inner.poll());
+
+        let keepalive = match self.config.keepalive {
+            Some(keepalive) => connection
+                .ping_pong()
+                .map(|ping_pong| Pinger::new(ping_pong, keepalive, self.config.timer.clone())),
+            None => None,
+        };
+        let idle = self
+            .config
+            .idle_timeout
+            .map(|timeout| Idle::new(timeout, self.config.timer.clone()));
+        let status = self.status.clone();
 
         // Spawn the worker task
-        let task = Background::connection(connection);
+        let task = Background::connection(connection, keepalive, idle, status.clone());
         self.executor.execute(task).map_err(|err| {
             warn!("error handshaking: {:?}", err);
             HandshakeError::Execute
         })?;
 
         // Create an instance of the service
-        let service = Connection::new(client, self.executor.clone());
+        let service = Connection::new(client, self.executor.clone(), status, &self.config);
 
         Ok(Async::Ready(service))
     }
@@ -255,19 +517,124 @@ where
 // ===== impl Error =====
 
 impl Error {
-    pub fn reason(&self) -> Option<h2::Reason> {
-        match self.kind {
-            Kind::Inner(ref h2) => h2.reason(),
-            _ => None,
+    fn new(kind: ErrorKind, source: Option<h2::Error>) -> Self {
+        Error {
+            kind,
+            source,
+            body: None,
         }
     }
+
+    /// Classify an error that affects the whole connection.
+    fn connection(err: h2::Error, status: &Status) -> Self {
+        let kind = if status.keepalive_expired() {
+            ErrorKind::KeepAliveTimeout
+        } else if err.is_io() {
+            ErrorKind::Io
+        } else {
+            match err.reason() {
+                Some(reason) => ErrorKind::closed(reason, status),
+                // h2 rejected the call itself.
+                None => ErrorKind::Protocol,
+            }
+        };
+
+        Error::new(kind, Some(err))
+    }
+
+    /// Classify an error on the stream with ID `id`.
+    ///
+    /// h2 fails every stream with a connection error before the `Background`
+    /// task observes it, so the connection's state cannot tell a stream error
+    /// from a connection error. A stream error is only attributed to the
+    /// connection when a received GOAWAY, or its reason, says so.
+    fn stream(
+        err: h2::Error,
+        id: h2::StreamId,
+        status: &Status,
+        body_error: Option<BodyError>,
+    ) -> Self {
+        let go_away = status.go_away();
+
+        // Streams fail when the connection does, so report why the connection
+        // failed instead.
+        let closed = go_away.map_or(false, |go_away| {
+            id > go_away.last_stream_id()
+                || (go_away.reason() != h2::Reason::NO_ERROR
+                    && err.reason() == Some(go_away.reason()))
+        });
+
+        if body_error.is_some() {
+            return Error {
+                kind: ErrorKind::Body,
+                source: Some(err),
+                body: body_error,
+            };
+        }
+
+        let kind = if status.keepalive_expired() {
+            ErrorKind::KeepAliveTimeout
+        } else if err.is_io() {
+            ErrorKind::Io
+        } else {
+            match err.reason() {
+                Some(reason) if closed => ErrorKind::closed(reason, status),
+                Some(reason) => ErrorKind::reset(reason, go_away),
+                None if closed => ErrorKind::Closed { go_away },
+                // h2 rejected a call made on the stream.
+                None => ErrorKind::Protocol,
+            }
+        };
+
+        Error::new(kind, Some(err))
+    }
+
+    /// Returns the error the request body failed with, if the request failed
+    /// because of its body.
+    pub fn body_error(&self) -> Option<&BodyError> {
+        self.body.as_ref()
+    }
+
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn reason(&self) -> Option<h2::Reason> {
+        self.source.as_ref().and_then(h2::Error::reason)
+    }
+
+    /// Returns `true` if the connection was closed because the peer failed to
+    /// acknowledge a keepalive PING in time.
+    pub fn is_keepalive_timeout(&self) -> bool {
+        self.kind == ErrorKind::KeepAliveTimeout
+    }
+
+    /// Returns `true` if no response was received before the request timed
+    /// out.
+    pub fn is_timeout(&self) -> bool {
+        self.kind == ErrorKind::Timeout
+    }
 }
 
+/// Classifies an error on a stream whose connection is still open.
+///
+/// h2 reports a stream error as its reset reason, whether the server reset
+/// the stream or the connection failed, so errors that may have come from the
+/// connection are classified by `Error::connection` and `Error::stream`.
 impl From<h2::Error> for Error {
     fn from(src: h2::Error) -> Self {
-        Error {
-            kind: Kind::Inner(src),
-        }
+        let kind = if src.is_io() {
+            ErrorKind::Io
+        } else {
+            match src.reason() {
+                Some(reason) => ErrorKind::Reset(reason),
+                // h2 rejected a call made on the stream.
+                None => ErrorKind::Protocol,
+            }
+        };
+
+        Error::new(kind, Some(src))
     }
 }
 
@@ -279,26 +646,93 @@ impl From<h2::Reason> for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.kind {
-            Kind::Inner(ref h2) => write!(f, "Error caused by underlying HTTP/2 error: {}", h2),
-            Kind::Spawn => write!(f, "Error spawning background task"),
+        match (&self.body, &self.source) {
+            (&Some(ref body), _) => write!(f, "{}: {}", self.kind, body),
+            (&None, &Some(ref h2)) => write!(f, "{}: {}", self.kind, h2),
+            (&None, &None) => fmt::Display::fmt(&self.kind, f),
         }
     }
 }
 
 impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
-        if let Kind::Inner(ref h2) = self.kind {
-            Some(h2)
-        } else {
-            None
+        match (&self.body, &self.source) {
+            (&Some(ref body), _) => Some(body),
+            (&None, &Some(ref h2)) => Some(h2),
+            (&None, &None) => None,
         }
     }
 
     fn description(&self) -> &str {
-        match self.kind {
-            Kind::Inner(ref h2) => h2.description(),
-            Kind::Spawn => "error spawning worker task",
+        self.kind.description()
+    }
+}
+
+// ===== impl ErrorKind =====
+
+impl ErrorKind {
+    /// Classify the closing of a connection with `reason`.
+    ///
+    /// Unless the server sent a GOAWAY, the client closed the connection
+    /// because the server violated the protocol.
+    fn closed(reason: h2::Reason, status: &Status) -> Self {
+        match status.go_away() {
+            None if reason == h2::Reason::FLOW_CONTROL_ERROR => ErrorKind::FlowControl,
+            go_away => ErrorKind::Closed { go_away },
+        }
+    }
+
+    /// Classify a stream error with `reason` that no GOAWAY accounts for.
+    ///
+    /// Flow control and compression errors fail the whole connection, except
+    /// that a flow control error may also reset a single stream. Either way,
+    /// they are classified the same whether the stream or the connection is
+    /// seen to fail first.
+    fn reset(reason: h2::Reason, go_away: Option<GoAway>) -> Self {
+        match reason {
+            h2::Reason::FLOW_CONTROL_ERROR => ErrorKind::FlowControl,
+            h2::Reason::COMPRESSION_ERROR => ErrorKind::Closed { go_away },
+            reason => ErrorKind::Reset(reason),
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            ErrorKind::Closed { .. } => "connection closed",
+            ErrorKind::Reset(_) => "stream reset by server",
+            ErrorKind::FlowControl => "flow control error",
+            ErrorKind::Timeout => "request timed out waiting for a response",
+            ErrorKind::KeepAliveTimeout => "connection closed after keepalive PING timed out",
+            ErrorKind::Body => "error sending request body",
+            ErrorKind::Io => "I/O error on connection",
+            ErrorKind::Protocol => "HTTP/2 protocol error",
+            ErrorKind::Spawn => "error spawning worker task",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Closed {
+                go_away: Some(ref go_away),
+            } => write!(
+                f,
+                "Connection closed by GOAWAY ({:?}, last stream {:?})",
+                go_away.reason(),
+                go_away.last_stream_id()
+            ),
+            ErrorKind::Closed { go_away: None } => write!(f, "Connection closed"),
+            ErrorKind::Reset(reason) => write!(f, "Stream reset by server: {:?}", reason),
+            ErrorKind::FlowControl => write!(f, "Flow control error"),
+            ErrorKind::Timeout => write!(f, "Request timed out waiting for a response"),
+            ErrorKind::KeepAliveTimeout => {
+                write!(f, "Connection closed after keepalive PING timed out")
+            }
+            ErrorKind::Body => write!(f, "Error sending request body"),
+            ErrorKind::Io => write!(f, "I/O error on connection"),
+            ErrorKind::Protocol => write!(f, "HTTP/2 protocol error"),
+            ErrorKind::Spawn => write!(f, "Error spawning background task"),
         }
     }
 }
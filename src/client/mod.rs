@@ -1,7 +1,24 @@
 mod background;
 mod connect;
 mod connection;
+mod pool;
+mod reconnect;
+mod retry;
+mod sniff;
+mod status;
 
 pub use self::background::Background;
 pub use self::connect::{Connect, ConnectFuture, ConnectError};
-pub use self::connection::{Connection, Handshake, ResponseFuture, Error, HandshakeError};
+pub use self::connection::{
+    Connection, Error, ErrorKind, Handshake, HandshakeError, RequestTimeout, ResponseFuture,
+};
+pub use self::pool::{Pool, PoolError, PoolFuture, Target};
+pub use self::reconnect::{Backoff, Reconnect};
+pub use self::retry::{ReplayBody, Retries, Retry, RetryError, RetryFuture};
+pub use self::status::{ConnectionState, GoAway};
+pub use error::BodyError;
+
+pub(crate) use self::background::CancelFlush;
+pub(crate) use self::connect::Config;
+pub(crate) use self::sniff::Sniff;
+pub(crate) use self::status::{InFlight, Status};
@@ -0,0 +1,450 @@
+use super::{Background, Connect, ConnectError, ConnectFuture, Connection, ConnectionState, Error,
+            InFlight, ResponseFuture};
+use {Body, RecvBody};
+
+use tower::MakeConnection;
+use tower_service::Service;
+
+use futures::future::Executor;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use http::uri::{Authority, Scheme, Uri};
+use http::{Request, Response};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{error, fmt};
+
+/// A client service that maintains connections to many targets.
+///
+/// Requests are dispatched to a connection to the `Target` named by their
+/// URI. Each target may have several connections: a new one is established
+/// whenever all of the existing connections have as many requests in flight
+/// as the server's SETTINGS_MAX_CONCURRENT_STREAMS allows. Concurrent
+/// requests that need a new connection share a single connection attempt.
+///
+/// Connections are removed from the pool once they stop accepting new
+/// requests, such as after the server sends a GOAWAY, or after they have been
+/// idle for the idle timeout.
+pub struct Pool<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    inner: Arc<Mutex<Inner<C, E, S>>>,
+}
+
+/// The origin server that a request is sent to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Target {
+    scheme: Scheme,
+    authority: Authority,
+}
+
+/// The response future of a `Pool`.
+pub struct PoolFuture<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    inner: Arc<Mutex<Inner<C, E, S>>>,
+    target: Option<Target>,
+    request: Option<Request<S>>,
+    state: State<C, E, S>,
+}
+
+/// Error produced by `Pool`.
+#[derive(Debug)]
+pub enum PoolError<T> {
+    /// The request URI does not have both a scheme and an authority.
+    Target,
+
+    /// A new connection could not be established.
+    Connect(ConnectError<T>),
+
+    /// The request failed.
+    Request(Error),
+}
+
+struct Inner<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    connect: Connect<Target, C, E, S>,
+    endpoints: HashMap<Target, Endpoint<C, E, S>>,
+    next_id: usize,
+}
+
+/// The connections to a single target.
+struct Endpoint<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    connections: Vec<Connection<C::Connection, E, S>>,
+    pending: Option<Pending<C, E, S>>,
+}
+
+/// A connection attempt shared by all of the requests waiting on it.
+struct Pending<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    id: usize,
+    future: ConnectFuture<Target, C, E, S>,
+    waiters: Vec<Task>,
+}
+
+enum State<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    /// Find a connection with capacity, or start establishing one.
+    Checkout,
+
+    /// Waiting on the connection attempt with the given ID.
+    Connecting(usize),
+
+    /// Waiting for the chosen connection to accept the request, which counts
+    /// as in flight on it in the meantime.
+    Ready {
+        connection: Connection<C::Connection, E, S>,
+        _reserved: InFlight,
+    },
+
+    /// Waiting for the response.
+    Waiting(ResponseFuture),
+
+    /// The request has no target.
+    Invalid,
+}
+
+enum Checkout<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    Ready(Connection<C::Connection, E, S>, InFlight),
+    Connecting(usize),
+}
+
+// ===== impl Pool =====
+
+impl<C, E, S> Pool<C, E, S>
+where
+    C: MakeConnection<Target> + 'static,
+    E: Executor<Background<C::Connection, S>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    /// Returns a `Pool` that establishes connections with `connect`.
+    pub fn new(connect: Connect<Target, C, E, S>) -> Self {
+        let inner = Inner {
+            connect,
+            endpoints: HashMap::new(),
+            next_id: 0,
+        };
+
+        Pool {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Sets how long a connection may have no requests in flight before it is
+    /// closed and removed from the pool.
+    ///
+    /// This replaces the idle timeout configured on the `Connect`, and applies
+    /// to connections established from now on.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.lock().unwrap().connect.set_idle_timeout(timeout);
+    }
+}
+
+impl<C, E, S> Clone for Pool<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    fn clone(&self) -> Self {
+        Pool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C, E, S> Service<Request<S>> for Pool<C, E, S>
+where
+    C: MakeConnection<Target> + 'static,
+    E: Executor<Background<C::Connection, S>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    type Response = Response<RecvBody>;
+    type Error = PoolError<C::Error>;
+    type Future = PoolFuture<C, E, S>;
+
+    /// A `Pool` is always ready: requests wait for a connection to their
+    /// target in the response future.
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Request<S>) -> Self::Future {
+        let target = Target::from_uri(request.uri());
+        let state = if target.is_some() {
+            State::Checkout
+        } else {
+            State::Invalid
+        };
+
+        PoolFuture {
+            inner: self.inner.clone(),
+            target,
+            request: Some(request),
+            state,
+        }
+    }
+}
+
+// ===== impl Target =====
+
+impl Target {
+    /// Returns the `Target` of a request to `uri`, if it has both a scheme and
+    /// an authority.
+    pub fn from_uri(uri: &Uri) -> Option<Self> {
+        let scheme = uri.scheme_part()?.clone();
+        let authority = uri.authority_part()?.clone();
+
+        Some(Target { scheme, authority })
+    }
+
+    /// Returns the scheme requests to this target are sent with.
+    pub fn scheme(&self) -> &Scheme {
+        &self.scheme
+    }
+
+    /// Returns the host and port of the origin server.
+    pub fn authority(&self) -> &Authority {
+        &self.authority
+    }
+}
+
+// ===== impl PoolFuture =====
+
+impl<C, E, S> Future for PoolFuture<C, E, S>
+where
+    C: MakeConnection<Target> + 'static,
+    E: Executor<Background<C::Connection, S>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    type Item = Response<RecvBody>;
+    type Error = PoolError<C::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Checkout => {
+                    let target = self.target.as_ref().expect("checkout without target");
+                    let mut inner = self.inner.lock().unwrap();
+
+                    match try_ready!(inner.checkout(target).map_err(PoolError::Connect)) {
+                        Checkout::Ready(connection, reserved) => State::Ready {
+                            connection,
+                            _reserved: reserved,
+                        },
+                        Checkout::Connecting(id) => State::Connecting(id),
+                    }
+                }
+                State::Connecting(id) => {
+                    let target = self.target.as_ref().expect("connecting without target");
+                    let mut inner = self.inner.lock().unwrap();
+
+                    try_ready!(inner.poll_pending(target, id).map_err(PoolError::Connect));
+
+                    // The connection may have been claimed by other requests
+                    // in the meantime, so check out again.
+                    State::Checkout
+                }
+                State::Ready {
+                    ref mut connection, ..
+                } => {
+                    try_ready!(connection.poll_ready().map_err(PoolError::Request));
+
+                    // The response future counts the request as in flight
+                    // from now on, so the reservation is released.
+                    let request = self.request.take().expect("polled after complete");
+                    State::Waiting(connection.call(request))
+                }
+                State::Waiting(ref mut future) => return future.poll().map_err(PoolError::Request),
+                State::Invalid => return Err(PoolError::Target),
+            };
+        }
+    }
+}
+
+impl<C, E, S> Drop for PoolFuture<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    fn drop(&mut self) {
+        // This may have been the task that the connection attempt notifies,
+        // so wake the other waiters to drive it instead.
+        if let (&State::Connecting(id), Some(ref target)) = (&self.state, &self.target) {
+            if let Ok(mut inner) = self.inner.lock() {
+                inner.notify_pending(target, id);
+            }
+        }
+    }
+}
+
+// ===== impl Inner =====
+
+impl<C, E, S> Inner<C, E, S>
+where
+    C: MakeConnection<Target> + 'static,
+    E: Executor<Background<C::Connection, S>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    /// Returns a connection to `target` with capacity, or the ID of the
+    /// connection attempt to wait on.
+    ///
+    /// The returned connection has a slot reserved for the request, so that
+    /// concurrent checkouts do not exceed its capacity.
+    fn checkout(&mut self, target: &Target) -> Poll<Checkout<C, E, S>, ConnectError<C::Error>> {
+        // Forget connections that will not accept new requests, and targets
+        // that have no connections left.
+        self.endpoints.retain(|_, endpoint| {
+            endpoint
+                .connections
+                .retain(|c| c.state() == ConnectionState::Open);
+            !endpoint.connections.is_empty() || endpoint.pending.is_some()
+        });
+
+        let endpoint = self
+            .endpoints
+            .entry(target.clone())
+            .or_insert_with(|| Endpoint {
+                connections: Vec::new(),
+                pending: None,
+            });
+
+        if let Some(connection) = endpoint.connections.iter().find(|c| c.has_capacity()) {
+            let reserved = connection.reserve();
+            return Ok(Async::Ready(Checkout::Ready(connection.clone(), reserved)));
+        }
+
+        if let Some(ref pending) = endpoint.pending {
+            return Ok(Async::Ready(Checkout::Connecting(pending.id)));
+        }
+
+        try_ready!(self.connect.poll_ready());
+
+        trace!("establishing new connection to {:?}", target);
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        endpoint.pending = Some(Pending {
+            id,
+            future: self.connect.call(target.clone()),
+            waiters: Vec::new(),
+        });
+
+        Ok(Async::Ready(Checkout::Connecting(id)))
+    }
+
+    /// Drive the connection attempt with the given ID, returning `Ready` once
+    /// it has established a connection.
+    fn poll_pending(&mut self, target: &Target, id: usize) -> Poll<(), ConnectError<C::Error>> {
+        let endpoint = match self.endpoints.get_mut(target) {
+            Some(endpoint) => endpoint,
+            None => return Ok(Async::Ready(())),
+        };
+
+        let result = match endpoint.pending {
+            Some(ref mut pending) if pending.id == id => pending.future.poll(),
+            // Another request has already completed the attempt.
+            _ => return Ok(Async::Ready(())),
+        };
+
+        let result = match result {
+            Ok(Async::NotReady) => {
+                let waiters = &mut endpoint.pending.as_mut().expect("pending").waiters;
+                if !waiters.iter().any(Task::will_notify_current) {
+                    waiters.push(task::current());
+                }
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(connection)) => {
+                endpoint.connections.push(connection);
+                Ok(Async::Ready(()))
+            }
+            Err(e) => Err(e),
+        };
+
+        for waiter in endpoint.pending.take().expect("pending").waiters {
+            waiter.notify();
+        }
+
+        result
+    }
+}
+
+impl<C, E, S> Inner<C, E, S>
+where
+    C: MakeConnection<Target>,
+    S: Body,
+{
+    fn notify_pending(&mut self, target: &Target, id: usize) {
+        if let Some(endpoint) = self.endpoints.get_mut(target) {
+            if let Some(ref mut pending) = endpoint.pending {
+                if pending.id == id {
+                    for waiter in pending.waiters.drain(..) {
+                        waiter.notify();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ===== impl PoolError =====
+
+impl<T> fmt::Display for PoolError<T>
+where
+    T: error::Error,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PoolError::Target => write!(f, "Request URI has no scheme or authority"),
+            PoolError::Connect(ref why) => write!(f, "Error establishing connection: {}", why),
+            PoolError::Request(ref why) => write!(f, "Error sending request: {}", why),
+        }
+    }
+}
+
+impl<T> error::Error for PoolError<T>
+where
+    T: error::Error,
+{
+    fn description(&self) -> &str {
+        match *self {
+            PoolError::Target => "request URI has no scheme or authority",
+            PoolError::Connect(_) => "error establishing connection",
+            PoolError::Request(_) => "error sending request",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            PoolError::Target => None,
+            PoolError::Connect(ref why) => Some(why),
+            PoolError::Request(ref why) => Some(why),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use super::{
+    Background, Connect, ConnectFuture, Connection, ConnectionState, Error, ResponseFuture,
+};
+use timer;
+use {Body, RecvBody};
+
+use tower::MakeConnection;
+use tower_service::Service;
+
+use futures::future::Executor;
+use futures::{Async, Future, Poll};
+use http::{Request, Response};
+use tokio_timer::Delay;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+use std::{cmp, error, fmt};
+
+/// A client service that maintains a connection to a single target.
+///
+/// The connection is established lazily, when the `Reconnect` is first
+/// polled. Whenever the connection fails, or the server starts draining it
+/// with a GOAWAY, a new connection is established in its place. Failed
+/// connection attempts, and connections that are lost, are retried after an
+/// exponential `Backoff`.
+///
+/// Connection errors are logged rather than returned, so `poll_ready` does
+/// not fail: it is `NotReady` until a connection has been established.
+pub struct Reconnect<A, C, E, S>
+where
+    C: MakeConnection<A>,
+    S: Body,
+{
+    connect: Connect<A, C, E, S>,
+    target: A,
+    backoff: Backoff,
+    failures: u32,
+    state: State<A, C, E, S>,
+}
+
+/// Configures the delay between failed connection attempts.
+///
+/// After each consecutive failure, the delay doubles, starting from `min` and
+/// never exceeding `max`. A random amount, up to `jitter` times the delay, is
+/// added to each delay so that many clients do not reconnect in lockstep.
+///
+/// Losing a connection counts as a failure, unless the connection had been
+/// open for at least `max`, in which case the delay starts again from `min`.
+/// This keeps a server that accepts connections and then immediately closes
+/// them from being reconnected to in a tight loop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    jitter: f64,
+}
+
+enum State<A, C, E, S>
+where
+    C: MakeConnection<A>,
+    S: Body,
+{
+    /// No connection has been established, and none is being established.
+    Idle,
+
+    /// Establishing a new connection.
+    Connecting(ConnectFuture<A, C, E, S>),
+
+    /// A connection has been established.
+    Connected {
+        connection: Connection<C::Connection, E, S>,
+        since: Instant,
+    },
+
+    /// Waiting to try connecting again after a failure.
+    Backoff(Delay),
+}
+
+// ===== impl Reconnect =====
+
+impl<A, C, E, S> Reconnect<A, C, E, S>
+where
+    A: Clone,
+    C: MakeConnection<A> + 'static,
+    C::Error: fmt::Debug,
+    E: Executor<Background<C::Connection, S>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    /// Returns a `Reconnect` that establishes connections to `target` with
+    /// `connect`.
+    ///
+    /// Backoff delays are driven by the `Timer` configured on `connect`.
+    pub fn new(connect: Connect<A, C, E, S>, target: A) -> Self {
+        Reconnect {
+            connect,
+            target,
+            backoff: Backoff::default(),
+            failures: 0,
+            state: State::Idle,
+        }
+    }
+
+    /// Sets the backoff between failed connection attempts.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// Note a failed connection attempt or a lost connection, returning the
+    /// backoff state.
+    fn fail(&mut self) -> State<A, C, E, S> {
+        let delay = self.backoff.delay(self.failures);
+        self.failures = self.failures.saturating_add(1);
+
+        debug!("reconnecting in {:?}", delay);
+        State::Backoff(self.connect.timer().delay(delay))
+    }
+}
+
+impl<A, C, E, S> Service<Request<S>> for Reconnect<A, C, E, S>
+where
+    A: Clone,
+    C: MakeConnection<A> + 'static,
+    C::Error: fmt::Debug,
+    E: Executor<Background<C::Connection, S>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    type Response = Response<RecvBody>;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Idle => match self.connect.poll_ready() {
+                    Ok(Async::Ready(())) => {
+                        State::Connecting(self.connect.call(self.target.clone()))
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        warn!("failed to connect: {:?}", e);
+                        self.fail()
+                    }
+                },
+                State::Connecting(ref mut future) => match future.poll() {
+                    Ok(Async::Ready(connection)) => {
+                        trace!("connected");
+                        State::Connected {
+                            connection,
+                            since: self.connect.timer().now(),
+                        }
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        warn!("failed to connect: {:?}", e);
+                        self.fail()
+                    }
+                },
+                State::Connected {
+                    ref mut connection,
+                    since,
+                } => {
+                    if connection.state() == ConnectionState::Open {
+                        match connection.poll_ready() {
+                            Ok(ready) => return Ok(ready),
+                            Err(e) => debug!("connection failed: {}", e),
+                        }
+                    } else {
+                        debug!("connection is no longer open");
+                    }
+
+                    // Only a connection that stayed healthy for a while
+                    // shows that the target has recovered.
+                    let open_for = self.connect.timer().now() - since;
+                    if open_for >= self.backoff.max {
+                        self.failures = 0;
+                    }
+
+                    // Requests already in flight keep their own handle to
+                    // the old connection, so it may be replaced once the
+                    // backoff elapses.
+                    self.fail()
+                }
+                State::Backoff(ref mut delay) => {
+                    if !timer::poll_elapsed(delay) {
+                        return Ok(Async::NotReady);
+                    }
+
+                    State::Idle
+                }
+            };
+        }
+    }
+
+    fn call(&mut self, request: Request<S>) -> Self::Future {
+        match self.state {
+            State::Connected {
+                ref mut connection, ..
+            } => connection.call(request),
+            _ => panic!("poll_ready must be called before call"),
+        }
+    }
+}
+
+// ===== impl Backoff =====
+
+impl Backoff {
+    /// Returns a `Backoff` that waits between `min` and `max`, plus up to
+    /// `jitter` times the delay.
+    ///
+    /// # Panics
+    ///
+    /// If `jitter` is negative or not finite.
+    pub fn new(min: Duration, max: Duration, jitter: f64) -> Self {
+        assert!(
+            jitter.is_finite() && jitter >= 0.0,
+            "jitter must be a non-negative number"
+        );
+
+        Backoff { min, max, jitter }
+    }
+
+    /// Returns the delay after `failures` consecutive failures.
+    fn delay(&self, failures: u32) -> Duration {
+        let base = self
+            .min
+            .checked_mul(1 << cmp::min(failures, 31))
+            .map_or(self.max, |delay| cmp::min(delay, self.max));
+
+        base.checked_add(jitter(base, self.jitter))
+            .unwrap_or(self.max)
+    }
+}
+
+impl Default for Backoff {
+    /// Waits between 100 milliseconds and 10 seconds, with up to 10% jitter.
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(10), 0.1)
+    }
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Returns a random duration of up to `factor` times `base`.
+fn jitter(base: Duration, factor: f64) -> Duration {
+    let secs = as_secs_f64(base) * factor * random();
+    Duration::from_nanos((secs * 1e9) as u64)
+}
+
+/// Returns a random number in `[0, 1)`.
+///
+/// The standard library seeds the keys of each thread's first `RandomState`
+/// randomly, and changes them for every `RandomState` built after it, so
+/// hashing nothing with a new one yields a different, unpredictable value
+/// each time. That is plenty for spreading out reconnects, though it is not
+/// suitable for anything security-sensitive.
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,587 @@
+use super::{Background, Connect, ConnectError, ConnectFuture, Connection, ConnectionState, Error,
+            ErrorKind, ResponseFuture};
+use {Body, RecvBody};
+
+use tower::MakeConnection;
+use tower_service::Service;
+
+use bytes::{Buf, Bytes, IntoBuf};
+use futures::future::Executor;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use h2;
+use http::{HeaderMap, Method, Request, Response, Uri, Version};
+
+use std::sync::{Arc, Mutex};
+use std::{error, fmt};
+
+/// Retries requests that the server is guaranteed not to have processed.
+///
+/// RFC 7540 guarantees that a request was not processed when its stream was
+/// reset with `REFUSED_STREAM`, or when its stream ID is higher than the last
+/// stream ID of a GOAWAY sent by the server. Such requests are retried on a
+/// fresh connection obtained from a `Connect`. All of the requests retried
+/// from the same connection share one replacement, which new requests are
+/// sent on as well.
+///
+/// Request bodies are buffered as they are sent, up to a limit, so that they
+/// may be replayed. Requests with larger bodies are not retried. Request
+/// extensions are not preserved across retries.
+///
+/// The number of retries is exposed in the response extensions as `Retries`.
+pub struct Retry<A, C, E, S>
+where
+    C: MakeConnection<A>,
+    S: Body,
+{
+    shared: Arc<Mutex<Shared<A, C, E, S>>>,
+    target: A,
+    connection: Option<Connection<C::Connection, E, ReplayBody<S>>>,
+    max_retries: usize,
+    max_buffer: usize,
+}
+
+/// The response future of a `Retry`.
+pub struct RetryFuture<A, C, E, S>
+where
+    C: MakeConnection<A>,
+    S: Body,
+{
+    state: State<A, C, E, S>,
+    head: Head,
+    replay: Arc<Mutex<Replay<S>>>,
+    shared: Arc<Mutex<Shared<A, C, E, S>>>,
+    target: A,
+    retries: usize,
+    max_retries: usize,
+}
+
+enum State<A, C, E, S>
+where
+    C: MakeConnection<A>,
+    S: Body,
+{
+    /// Waiting for the response to a request sent on `connection`.
+    Waiting {
+        connection: Connection<C::Connection, E, ReplayBody<S>>,
+        future: ResponseFuture,
+    },
+
+    /// Waiting for a connection other than `failed` to retry on.
+    Connect {
+        failed: Connection<C::Connection, E, ReplayBody<S>>,
+    },
+
+    /// Waiting for a new connection to accept the request.
+    Ready(Connection<C::Connection, E, ReplayBody<S>>),
+}
+
+/// The connection shared by a `Retry` and its response futures.
+struct Shared<A, C, E, S>
+where
+    C: MakeConnection<A>,
+    S: Body,
+{
+    connect: Connect<A, C, E, ReplayBody<S>>,
+    connection: Option<Connection<C::Connection, E, ReplayBody<S>>>,
+    connecting: Option<ConnectFuture<A, C, E, ReplayBody<S>>>,
+
+    /// Tasks waiting for `connecting` to complete.
+    waiting: Vec<Task>,
+}
+
+/// The parts of a request needed to send it again.
+struct Head {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+}
+
+/// A request body that records its data so that it may be sent again.
+pub struct ReplayBody<B> {
+    replay: Arc<Mutex<Replay<B>>>,
+    generation: usize,
+    pos: usize,
+}
+
+/// State shared by all of the `ReplayBody`s of a request.
+struct Replay<B> {
+    body: B,
+
+    /// The data read from `body` so far, while it fits in the limit.
+    buf: Vec<Bytes>,
+    buffered: usize,
+    max_buffer: usize,
+
+    /// Cleared once `buf` is abandoned because it grew past `max_buffer`.
+    replayable: bool,
+
+    /// Set once `body` has yielded all of its data.
+    data_done: bool,
+
+    /// Set once `body` has yielded its trailers, if any.
+    trailers: Option<Option<HeaderMap>>,
+
+    /// Only the `ReplayBody` of the most recent attempt may read from `body`.
+    generation: usize,
+}
+
+/// The number of times a request was retried.
+///
+/// Inserted into the extensions of responses returned by `Retry`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retries(usize);
+
+/// Error produced by `Retry`.
+#[derive(Debug)]
+pub enum RetryError<T> {
+    /// A new connection could not be established.
+    Connect(ConnectError<T>),
+
+    /// The request failed, and could not be retried.
+    Request(Error),
+}
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+const DEFAULT_MAX_BUFFER: usize = 64 * 1024;
+
+// ===== impl Retry =====
+
+impl<A, C, E, S> Retry<A, C, E, S>
+where
+    A: Clone,
+    C: MakeConnection<A> + 'static,
+    E: Executor<Background<C::Connection, ReplayBody<S>>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    /// Returns a `Retry` that establishes connections to `target` with
+    /// `connect`.
+    ///
+    /// A connection is established when the `Retry` is first polled, and
+    /// again whenever the current connection stops accepting new requests.
+    pub fn new(connect: Connect<A, C, E, ReplayBody<S>>, target: A) -> Self {
+        let shared = Shared {
+            connect,
+            connection: None,
+            connecting: None,
+            waiting: Vec::new(),
+        };
+
+        Retry {
+            shared: Arc::new(Mutex::new(shared)),
+            target,
+            connection: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_buffer: DEFAULT_MAX_BUFFER,
+        }
+    }
+
+    /// Sets the maximum number of times a request is retried.
+    ///
+    /// Defaults to 3.
+    pub fn set_max_retries(&mut self, max: usize) {
+        self.max_retries = max;
+    }
+
+    /// Sets the maximum number of request body bytes buffered for replay.
+    ///
+    /// Requests with larger bodies are not retried. Defaults to 64 KiB.
+    pub fn set_max_buffer(&mut self, max: usize) {
+        self.max_buffer = max;
+    }
+}
+
+impl<A, C, E, S> Service<Request<S>> for Retry<A, C, E, S>
+where
+    A: Clone,
+    C: MakeConnection<A> + 'static,
+    E: Executor<Background<C::Connection, ReplayBody<S>>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    type Response = Response<RecvBody>;
+    type Error = RetryError<C::Error>;
+    type Future = RetryFuture<A, C, E, S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let connection = try_ready!(self
+            .shared
+            .lock()
+            .unwrap()
+            .poll_connection(&self.target, None)
+            .map_err(RetryError::Connect));
+
+        // Keep polling the same handle until the connection is replaced.
+        let replaced = match self.connection {
+            Some(ref current) => !current.is_same(&connection),
+            None => true,
+        };
+        if replaced {
+            self.connection = Some(connection);
+        }
+
+        self.connection
+            .as_mut()
+            .expect("connection")
+            .poll_ready()
+            .map_err(RetryError::Request)
+    }
+
+    fn call(&mut self, request: Request<S>) -> Self::Future {
+        let (parts, body) = request.into_parts();
+        let head = Head {
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            version: parts.version,
+            headers: parts.headers.clone(),
+        };
+
+        let replay = Arc::new(Mutex::new(Replay::new(body, self.max_buffer)));
+        let body = Replay::next(&replay);
+
+        let mut connection = self
+            .connection
+            .clone()
+            .expect("poll_ready must be called before call");
+        let future = connection.call(Request::from_parts(parts, body));
+
+        RetryFuture {
+            state: State::Waiting { connection, future },
+            head,
+            replay,
+            shared: self.shared.clone(),
+            target: self.target.clone(),
+            retries: 0,
+            max_retries: self.max_retries,
+        }
+    }
+}
+
+// ===== impl RetryFuture =====
+
+impl<A, C, E, S> Future for RetryFuture<A, C, E, S>
+where
+    A: Clone,
+    C: MakeConnection<A> + 'static,
+    E: Executor<Background<C::Connection, ReplayBody<S>>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    type Item = Response<RecvBody>;
+    type Error = RetryError<C::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Waiting {
+                    ref connection,
+                    ref mut future,
+                } => match future.poll() {
+                    Ok(Async::Ready(mut response)) => {
+                        response.extensions_mut().insert(Retries(self.retries));
+                        return Ok(Async::Ready(response));
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        let unprocessed = is_unprocessed(&err, future.stream_id());
+                        let replayable = self.replay.lock().unwrap().replayable;
+
+                        if !unprocessed || !replayable || self.retries >= self.max_retries {
+                            return Err(RetryError::Request(err));
+                        }
+
+                        self.retries += 1;
+                        debug!("request was not processed; retrying (attempt {})", self.retries);
+                        State::Connect {
+                            failed: connection.clone(),
+                        }
+                    }
+                },
+                State::Connect { ref failed } => {
+                    let connection = try_ready!(self
+                        .shared
+                        .lock()
+                        .unwrap()
+                        .poll_connection(&self.target, Some(failed))
+                        .map_err(RetryError::Connect));
+                    State::Ready(connection)
+                }
+                State::Ready(ref mut connection) => {
+                    try_ready!(connection.poll_ready().map_err(RetryError::Request));
+
+                    let body = Replay::next(&self.replay);
+                    let future = connection.call(self.head.request(body));
+
+                    State::Waiting {
+                        connection: connection.clone(),
+                        future,
+                    }
+                }
+            };
+        }
+    }
+}
+
+/// Returns `true` if the server is guaranteed not to have processed a request
+/// that failed with `err`.
+///
+/// Only a `REFUSED_STREAM` reset sent by the server counts. The client may
+/// reset a stream with the same reason itself, such as when the request body
+/// fails, but the server may have processed that request already.
+fn is_unprocessed(err: &Error, stream_id: Option<h2::StreamId>) -> bool {
+    match (err.kind(), stream_id) {
+        (ErrorKind::Reset(h2::Reason::REFUSED_STREAM), _) => true,
+        (ErrorKind::Closed { go_away: Some(go_away) }, Some(id)) => id > go_away.last_stream_id(),
+        // The request was never sent, because the server had already sent a
+        // GOAWAY.
+        (ErrorKind::Closed { go_away: Some(_) }, None) => true,
+        _ => false,
+    }
+}
+
+// ===== impl Shared =====
+
+impl<A, C, E, S> Shared<A, C, E, S>
+where
+    A: Clone,
+    C: MakeConnection<A> + 'static,
+    E: Executor<Background<C::Connection, ReplayBody<S>>> + Clone,
+    S: Body + 'static,
+    S::Error: Into<Box<dyn error::Error>>,
+{
+    /// Returns a connection that accepts new requests, establishing one if
+    /// the current connection does not, or if it is `failed`.
+    ///
+    /// Every task waiting for the connection to be established is notified
+    /// once it has been.
+    fn poll_connection(
+        &mut self,
+        target: &A,
+        failed: Option<&Connection<C::Connection, E, ReplayBody<S>>>,
+    ) -> Poll<Connection<C::Connection, E, ReplayBody<S>>, ConnectError<C::Error>> {
+        loop {
+            let connected = match self.connecting {
+                Some(ref mut connecting) => match connecting.poll() {
+                    Ok(Async::Ready(connection)) => Ok(connection),
+                    Ok(Async::NotReady) => {
+                        if !self.waiting.iter().any(Task::will_notify_current) {
+                            self.waiting.push(task::current());
+                        }
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => Err(err),
+                },
+                None => {
+                    if let Some(ref connection) = self.connection {
+                        let is_failed = failed.map_or(false, |failed| failed.is_same(connection));
+                        if !is_failed && connection.state() == ConnectionState::Open {
+                            return Ok(Async::Ready(connection.clone()));
+                        }
+                    }
+
+                    // The current connection will not accept new requests,
+                    // so replace it.
+                    self.connection = None;
+                    try_ready!(self.connect.poll_ready());
+                    self.connecting = Some(self.connect.call(target.clone()));
+                    continue;
+                }
+            };
+
+            self.connecting = None;
+            for task in self.waiting.drain(..) {
+                task.notify();
+            }
+
+            let connection = connected?;
+            self.connection = Some(connection.clone());
+            return Ok(Async::Ready(connection));
+        }
+    }
+}
+
+// ===== impl Head =====
+
+impl Head {
+    fn request<B>(&self, body: B) -> Request<B> {
+        let mut request = Request::new(body);
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = self.uri.clone();
+        *request.version_mut() = self.version;
+        *request.headers_mut() = self.headers.clone();
+        request
+    }
+}
+
+// ===== impl ReplayBody =====
+
+impl<B> Body for ReplayBody<B>
+where
+    B: Body,
+{
+    type Data = <Bytes as IntoBuf>::Buf;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        let replay = self.replay.lock().unwrap();
+
+        if self.generation != replay.generation {
+            return true;
+        }
+
+        if self.pos < replay.buf.len() {
+            return false;
+        }
+
+        match replay.trailers {
+            Some(ref trailers) => trailers.is_none(),
+            None => replay.body.is_end_stream(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let mut replay = self.replay.lock().unwrap();
+
+        // A newer attempt has taken over the body.
+        if self.generation != replay.generation {
+            return Ok(Async::Ready(None));
+        }
+
+        // Replay data sent by earlier attempts first.
+        if self.pos < replay.buf.len() {
+            let data = replay.buf[self.pos].clone();
+            self.pos += 1;
+            return Ok(Async::Ready(Some(data.into_buf())));
+        }
+
+        if replay.data_done {
+            return Ok(Async::Ready(None));
+        }
+
+        let data: Bytes = match try_ready!(replay.body.poll_data()) {
+            Some(data) => data.collect(),
+            None => {
+                replay.data_done = true;
+                return Ok(Async::Ready(None));
+            }
+        };
+
+        if replay.replayable {
+            if replay.buffered + data.len() <= replay.max_buffer {
+                replay.buffered += data.len();
+                replay.buf.push(data.clone());
+                self.pos += 1;
+            } else {
+                trace!("request body too large to replay");
+                replay.replayable = false;
+                replay.buf = Vec::new();
+            }
+        }
+
+        Ok(Async::Ready(Some(data.into_buf())))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        let mut replay = self.replay.lock().unwrap();
+
+        if self.generation != replay.generation {
+            return Ok(Async::Ready(None));
+        }
+
+        if let Some(ref trailers) = replay.trailers {
+            return Ok(Async::Ready(trailers.clone()));
+        }
+
+        let trailers = try_ready!(replay.body.poll_trailers());
+        replay.trailers = Some(trailers.clone());
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for ReplayBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReplayBody")
+            .field("generation", &self.generation)
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+// ===== impl Replay =====
+
+impl<B> Replay<B> {
+    fn new(body: B, max_buffer: usize) -> Self {
+        Replay {
+            body,
+            buf: Vec::new(),
+            buffered: 0,
+            max_buffer,
+            replayable: true,
+            data_done: false,
+            trailers: None,
+            generation: 0,
+        }
+    }
+
+    /// Returns a `ReplayBody` for the next attempt, which supersedes those of
+    /// all earlier attempts.
+    fn next(replay: &Arc<Mutex<Self>>) -> ReplayBody<B> {
+        let generation = {
+            let mut replay = replay.lock().unwrap();
+            replay.generation += 1;
+            replay.generation
+        };
+
+        ReplayBody {
+            replay: replay.clone(),
+            generation,
+            pos: 0,
+        }
+    }
+}
+
+// ===== impl Retries =====
+
+impl Retries {
+    /// Returns the number of times the request was retried.
+    pub fn count(&self) -> usize {
+        self.0
+    }
+}
+
+// ===== impl RetryError =====
+
+impl<T> fmt::Display for RetryError<T>
+where
+    T: error::Error,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RetryError::Connect(ref why) => write!(f, "Error establishing connection: {}", why),
+            RetryError::Request(ref why) => write!(f, "Error sending request: {}", why),
+        }
+    }
+}
+
+impl<T> error::Error for RetryError<T>
+where
+    T: error::Error,
+{
+    fn description(&self) -> &str {
+        match *self {
+            RetryError::Connect(_) => "error establishing connection",
+            RetryError::Request(_) => "error sending request",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RetryError::Connect(ref why) => Some(why),
+            RetryError::Request(ref why) => Some(why),
+        }
+    }
+}
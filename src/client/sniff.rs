@@ -0,0 +1,201 @@
+use super::status::{GoAway, Status};
+
+use futures::Poll;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// The length of an HTTP/2.0 frame header.
+const HEADER_LEN: usize = 9;
+
+/// The frame type of a SETTINGS frame.
+const SETTINGS: u8 = 0x4;
+
+/// The frame type of a GOAWAY frame.
+const GO_AWAY: u8 = 0x7;
+
+/// The flag set on SETTINGS frames that acknowledge the peer's settings.
+const ACK: u8 = 0x1;
+
+/// The identifier of SETTINGS_MAX_CONCURRENT_STREAMS.
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+
+/// The most SETTINGS payload that is kept; far more than any real server
+/// sends.
+const MAX_SETTINGS_LEN: usize = 6 * 64;
+
+/// Wraps a client connection's transport, watching the frames received from
+/// the server for connection-level state that h2 does not expose.
+///
+/// h2 0.1 reports neither the GOAWAY frames nor the SETTINGS it receives.
+/// Only frame headers are parsed to split the byte stream into frames, and
+/// only the payloads of SETTINGS and GOAWAY frames on stream 0 are decoded.
+/// Frames are not validated here; h2 fails the connection on any that are
+/// malformed, after which nothing observed here is used.
+pub(crate) struct Sniff<T> {
+    inner: T,
+    parser: Parser,
+    status: Arc<Status>,
+}
+
+/// Incrementally splits the received byte stream into frames.
+struct Parser {
+    /// The header of the current frame.
+    head: [u8; HEADER_LEN],
+
+    /// How much of `head` has been read.
+    head_len: usize,
+
+    /// How much of the current frame's payload has yet to be read.
+    remaining: usize,
+
+    /// The leading bytes of the current frame's payload, if it is a frame
+    /// of interest.
+    payload: Vec<u8>,
+}
+
+// ===== impl Sniff =====
+
+impl<T> Sniff<T> {
+    pub fn new(inner: T, status: Arc<Status>) -> Self {
+        Sniff {
+            inner,
+            parser: Parser::new(),
+            status,
+        }
+    }
+}
+
+impl<T: Read> Read for Sniff<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.parser.parse(&buf[..n], &self.status);
+        Ok(n)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Sniff<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: Write> Write for Sniff<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Sniff<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+// ===== impl Parser =====
+
+impl Parser {
+    fn new() -> Self {
+        Parser {
+            head: [0; HEADER_LEN],
+            head_len: 0,
+            remaining: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, mut buf: &[u8], status: &Status) {
+        while !buf.is_empty() {
+            if self.head_len < HEADER_LEN {
+                let n = cmp::min(HEADER_LEN - self.head_len, buf.len());
+                self.head[self.head_len..self.head_len + n].copy_from_slice(&buf[..n]);
+                self.head_len += n;
+                buf = &buf[n..];
+
+                if self.head_len == HEADER_LEN {
+                    self.remaining = (self.head[0] as usize) << 16
+                        | (self.head[1] as usize) << 8
+                        | self.head[2] as usize;
+                    self.payload.clear();
+
+                    if self.remaining == 0 {
+                        self.end_frame(status);
+                    }
+                }
+
+                continue;
+            }
+
+            let n = cmp::min(self.remaining, buf.len());
+            let wanted = self.capture_len().saturating_sub(self.payload.len());
+            self.payload.extend_from_slice(&buf[..cmp::min(n, wanted)]);
+            self.remaining -= n;
+            buf = &buf[n..];
+
+            if self.remaining == 0 {
+                self.end_frame(status);
+            }
+        }
+    }
+
+    /// Returns the type of the current frame, if it is a connection-level
+    /// frame whose payload is of interest.
+    fn interest(&self) -> Option<u8> {
+        let stream_id = read_u32(&self.head[5..9]) & !(1 << 31);
+        if stream_id != 0 {
+            return None;
+        }
+
+        match self.head[3] {
+            SETTINGS if self.head[4] & ACK == 0 => Some(SETTINGS),
+            GO_AWAY => Some(GO_AWAY),
+            _ => None,
+        }
+    }
+
+    /// Returns how much of the current frame's payload must be kept.
+    fn capture_len(&self) -> usize {
+        match self.interest() {
+            Some(SETTINGS) => MAX_SETTINGS_LEN,
+            // The last stream ID and error code.
+            Some(GO_AWAY) => 8,
+            _ => 0,
+        }
+    }
+
+    fn end_frame(&mut self, status: &Status) {
+        self.head_len = 0;
+
+        match self.interest() {
+            Some(SETTINGS) => {
+                for setting in self.payload.chunks(6).filter(|s| s.len() == 6) {
+                    let id = (setting[0] as u16) << 8 | setting[1] as u16;
+                    if id == MAX_CONCURRENT_STREAMS {
+                        let max = read_u32(&setting[2..6]);
+                        trace!("server MAX_CONCURRENT_STREAMS={}", max);
+                        status.set_max_concurrent_streams(max);
+                    }
+                }
+            }
+            Some(GO_AWAY) if self.payload.len() == 8 => {
+                let last_stream_id = read_u32(&self.payload[0..4]) & !(1 << 31);
+                let reason = read_u32(&self.payload[4..8]);
+
+                let go_away = GoAway::new(last_stream_id.into(), reason.into());
+                debug!("received {:?}", go_away);
+                status.set_go_away(go_away);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    (buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | buf[3] as u32
+}
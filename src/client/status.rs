@@ -0,0 +1,177 @@
+use futures::task::AtomicTask;
+use h2;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::usize;
+
+/// The state of a client connection, as last observed by the task driving
+/// it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// New requests may be sent on the connection.
+    Open,
+
+    /// The server has sent a GOAWAY, so it will not process requests on new
+    /// streams. Requests already in flight may still complete.
+    Draining,
+
+    /// The connection has closed.
+    Closed,
+}
+
+/// A GOAWAY frame received from the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GoAway {
+    last_stream_id: h2::StreamId,
+    reason: h2::Reason,
+}
+
+/// Connection health shared by a client `Connection`, its `ResponseFuture`s,
+/// and the `Background` task driving the connection.
+#[derive(Debug)]
+pub(crate) struct Status {
+    /// Set once the peer failed to acknowledge a keepalive PING in time.
+    keepalive_expired: AtomicBool,
+
+    /// The most recent GOAWAY received from the server.
+    go_away: Mutex<Option<GoAway>>,
+
+    /// Set once the connection task has completed.
+    closed: AtomicBool,
+
+    /// The server's SETTINGS_MAX_CONCURRENT_STREAMS, or `usize::MAX` if it has
+    /// not set one.
+    max_concurrent_streams: AtomicUsize,
+
+    /// The number of live `InFlight` guards.
+    in_flight: AtomicUsize,
+
+    /// Set when `in_flight` drops to zero, until the connection task
+    /// observes it.
+    emptied: AtomicBool,
+
+    /// The connection task, notified when `in_flight` drops to zero.
+    task: AtomicTask,
+}
+
+/// Counts a request as in flight on a connection until it is dropped.
+///
+/// Held by a `ResponseFuture`, and then by the `RecvBody` of its response
+/// until the body has been received.
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    status: Arc<Status>,
+}
+
+// ===== impl GoAway =====
+
+impl GoAway {
+    pub(crate) fn new(last_stream_id: h2::StreamId, reason: h2::Reason) -> Self {
+        GoAway {
+            last_stream_id,
+            reason,
+        }
+    }
+
+    /// Returns the highest stream ID that the server may have processed.
+    ///
+    /// Requests sent on streams with higher IDs were not processed, and may
+    /// be safely retried on another connection.
+    pub fn last_stream_id(&self) -> h2::StreamId {
+        self.last_stream_id
+    }
+
+    /// Returns the reason the server gave for closing the connection.
+    pub fn reason(&self) -> h2::Reason {
+        self.reason
+    }
+}
+
+// ===== impl Status =====
+
+impl Status {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Status {
+            keepalive_expired: AtomicBool::new(false),
+            go_away: Mutex::new(None),
+            closed: AtomicBool::new(false),
+            max_concurrent_streams: AtomicUsize::new(usize::MAX),
+            in_flight: AtomicUsize::new(0),
+            emptied: AtomicBool::new(false),
+            task: AtomicTask::new(),
+        })
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        if self.closed.load(Ordering::SeqCst) {
+            ConnectionState::Closed
+        } else if self.go_away().is_some() {
+            ConnectionState::Draining
+        } else {
+            ConnectionState::Open
+        }
+    }
+
+    pub fn keepalive_expired(&self) -> bool {
+        self.keepalive_expired.load(Ordering::SeqCst)
+    }
+
+    pub fn set_keepalive_expired(&self) {
+        self.keepalive_expired.store(true, Ordering::SeqCst);
+    }
+
+    pub fn go_away(&self) -> Option<GoAway> {
+        *self.go_away.lock().unwrap()
+    }
+
+    pub fn set_go_away(&self, go_away: GoAway) {
+        *self.go_away.lock().unwrap() = Some(go_away);
+    }
+
+    pub fn set_closed(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_max_concurrent_streams(&self, max: u32) {
+        self.max_concurrent_streams.store(max as usize, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if another request may be sent without waiting for the
+    /// server's concurrency limit.
+    pub fn has_capacity(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) < self.max_concurrent_streams.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the last in-flight request has completed since this
+    /// was last called.
+    ///
+    /// The current task is notified when the last in-flight request
+    /// completes.
+    pub fn poll_emptied(&self) -> bool {
+        self.task.register();
+        self.emptied.swap(false, Ordering::SeqCst)
+    }
+}
+
+// ===== impl InFlight =====
+
+impl InFlight {
+    pub fn new(status: Arc<Status>) -> Self {
+        status.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight { status }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.status.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.status.emptied.store(true, Ordering::SeqCst);
+            self.status.task.notify();
+        }
+    }
+}
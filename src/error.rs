@@ -1,16 +1,205 @@
 use h2;
 
-pub(crate) fn reason_from_dyn_error(err: &(dyn std::error::Error + 'static)) -> h2::Reason {
-    let mut cause = Some(err);
-    while let Some(err) = cause {
-        if let Some(h2_err) = err.downcast_ref::<h2::Error>() {
-            return h2_err
-                .reason()
-                .unwrap_or(h2::Reason::INTERNAL_ERROR);
+use std::collections::HashMap;
+use std::error::Error;
+use std::{fmt, io};
+
+/// The error a body failed with while it was being sent.
+///
+/// Body errors are not required to be `Send` or `Sync`. The original error is
+/// kept if it is an `h2::Error` or an `io::Error`; any other error is captured
+/// by its message, along with the messages of its sources.
+#[derive(Debug)]
+pub struct BodyError {
+    source: Source,
+    reason: h2::Reason,
+}
+
+#[derive(Debug)]
+enum Source {
+    Error(Box<dyn Error + Send + Sync>),
+    Message(String),
+}
+
+/// Classifies an error as the reason to reset a stream with.
+///
+/// Error types that implement `ResetReason` are consulted once they have been
+/// registered with a `ResetReasons`.
+pub trait ResetReason {
+    /// Returns the reason to reset a stream with, or `None` to keep looking
+    /// through the error's sources.
+    fn reset_reason(&self) -> Option<h2::Reason>;
+}
+
+/// Determines the reason a stream is reset with when a service or body fails.
+///
+/// Each error in the failure's chain of sources is classified in turn, and
+/// the first reason found is used:
+///
+/// - `h2::Error`s use their own reason, or `INTERNAL_ERROR` if they have none.
+/// - Registered `ResetReason` types use their `reset_reason`.
+/// - `io::Error`s use the reason registered for their `ErrorKind`.
+///
+/// If no error in the chain is classified, the stream is reset with
+/// `INTERNAL_ERROR`.
+#[derive(Clone, Default)]
+pub struct ResetReasons {
+    classifiers: Vec<fn(&(dyn Error + 'static)) -> Option<h2::Reason>>,
+    io_errors: HashMap<io::ErrorKind, h2::Reason>,
+}
+
+// ===== impl BodyError =====
+
+impl BodyError {
+    pub(crate) fn new(err: Box<dyn Error>, reason: h2::Reason) -> Self {
+        let source = match shareable(err) {
+            Ok(err) => Source::Error(err),
+            Err(err) => {
+                let mut message = err.to_string();
+                let mut cause = err.source();
+                while let Some(err) = cause {
+                    message.push_str(": ");
+                    message.push_str(&err.to_string());
+                    cause = err.source();
+                }
+                Source::Message(message)
+            }
+        };
+
+        BodyError { source, reason }
+    }
+
+    /// Returns the reason the body's stream was reset with.
+    pub fn reason(&self) -> h2::Reason {
+        self.reason
+    }
+
+    /// Returns the error the body failed with, if it was kept.
+    pub fn get_ref(&self) -> Option<&(dyn Error + Send + Sync + 'static)> {
+        match self.source {
+            Source::Error(ref err) => Some(&**err),
+            Source::Message(_) => None,
+        }
+    }
+
+    /// Returns the error the body failed with, if it was kept.
+    pub fn into_inner(self) -> Option<Box<dyn Error + Send + Sync>> {
+        match self.source {
+            Source::Error(err) => Some(err),
+            Source::Message(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            Source::Error(ref err) => fmt::Display::fmt(err, f),
+            Source::Message(ref message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for BodyError {
+    fn description(&self) -> &str {
+        "error sending body"
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.source {
+            Source::Error(ref err) => Some(&**err),
+            Source::Message(_) => None,
+        }
+    }
+}
+
+/// Recovers `err` as a `Send + Sync` error, if it is of a type known to be.
+fn shareable(err: Box<dyn Error>) -> Result<Box<dyn Error + Send + Sync>, Box<dyn Error>> {
+    let err = match err.downcast::<h2::Error>() {
+        Ok(err) => return Ok(err),
+        Err(err) => err,
+    };
+
+    match err.downcast::<io::Error>() {
+        Ok(err) => Ok(err),
+        Err(err) => Err(err),
+    }
+}
+
+// ===== impl ResetReason =====
+
+impl ResetReason for h2::Error {
+    fn reset_reason(&self) -> Option<h2::Reason> {
+        Some(self.reason().unwrap_or(h2::Reason::INTERNAL_ERROR))
+    }
+}
+
+// ===== impl ResetReasons =====
+
+impl ResetReasons {
+    /// Returns a `ResetReasons` that only classifies `h2::Error`s.
+    pub fn new() -> Self {
+        ResetReasons::default()
+    }
+
+    /// Classify errors of type `E` with its `ResetReason` implementation.
+    pub fn register<E>(&mut self) -> &mut Self
+    where
+        E: ResetReason + Error + 'static,
+    {
+        self.classifiers.push(classify::<E>);
+        self
+    }
+
+    /// Reset streams that fail with an `io::Error` of `kind` with `reason`.
+    pub fn io_error(&mut self, kind: io::ErrorKind, reason: h2::Reason) -> &mut Self {
+        self.io_errors.insert(kind, reason);
+        self
+    }
+
+    /// Returns the reason to reset a stream that failed with `err`.
+    pub(crate) fn reason(&self, err: &(dyn Error + 'static)) -> h2::Reason {
+        let mut cause = Some(err);
+        while let Some(err) = cause {
+            if let Some(reason) = self.classify(err) {
+                return reason;
+            }
+            cause = err.source();
+        }
+
+        // unknown error
+        h2::Reason::INTERNAL_ERROR
+    }
+
+    fn classify(&self, err: &(dyn Error + 'static)) -> Option<h2::Reason> {
+        if let Some(reason) = classify::<h2::Error>(err) {
+            return Some(reason);
         }
-        cause = err.source();
+
+        for classify in &self.classifiers {
+            if let Some(reason) = classify(err) {
+                return Some(reason);
+            }
+        }
+
+        err.downcast_ref::<io::Error>()
+            .and_then(|err| self.io_errors.get(&err.kind()))
+            .cloned()
     }
+}
+
+impl fmt::Debug for ResetReasons {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResetReasons")
+            .field("classifiers", &self.classifiers.len())
+            .field("io_errors", &self.io_errors)
+            .finish()
+    }
+}
 
-    // unknown error
-    h2::Reason::INTERNAL_ERROR
+fn classify<E>(err: &(dyn Error + 'static)) -> Option<h2::Reason>
+where
+    E: ResetReason + Error + 'static,
+{
+    err.downcast_ref::<E>().and_then(ResetReason::reset_reason)
 }
@@ -1,10 +1,14 @@
 use buf::SendBuf;
-use Body;
+use error::BodyError;
+use {Body, ResetReasons};
 
 use futures::{Async, Future, Poll};
 use h2::{self, SendStream};
 use http::HeaderMap;
 
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
 /// Flush a body to the HTTP/2.0 send stream
 pub(crate) struct Flush<S>
 where
@@ -13,6 +17,22 @@ where
     h2: SendStream<SendBuf<S::Data>>,
     body: S,
     state: FlushState,
+    reasons: Arc<ResetReasons>,
+
+    /// Receives the error the body fails with, if any.
+    body_error: Option<Arc<Mutex<Option<BodyError>>>>,
+}
+
+/// Why flushing a body failed.
+pub(crate) enum FlushError {
+    /// The stream was reset, either by the peer, or because the body failed.
+    Reset(h2::Reason),
+
+    /// The connection closed before the body was sent.
+    Closed,
+
+    /// h2 failed to send the body.
+    H2(h2::Error),
 }
 
 enum FlushState {
@@ -33,47 +53,66 @@ where
     S: Body,
     S::Error: Into<Box<dyn std::error::Error>>,
 {
-    pub fn new(src: S, dst: SendStream<SendBuf<S::Data>>) -> Self {
+    pub fn new(src: S, dst: SendStream<SendBuf<S::Data>>, reasons: Arc<ResetReasons>) -> Self {
         Flush {
             h2: dst,
             body: src,
             state: FlushState::Data,
+            reasons,
+            body_error: None,
         }
     }
 
+    /// Stores the error the body fails with in `slot`, before the stream is
+    /// reset.
+    pub fn set_body_error(&mut self, slot: Arc<Mutex<Option<BodyError>>>) {
+        self.body_error = Some(slot);
+    }
+
+    /// Reset the stream, abandoning the rest of the body.
+    pub fn send_reset(&mut self, reason: h2::Reason) {
+        self.h2.send_reset(reason);
+    }
+
     /// Try to flush the body.
-    fn poll_complete(&mut self) -> Poll<(), h2::Error> {
+    pub(crate) fn poll_complete(&mut self) -> Poll<(), FlushError> {
+        while !try_ready!(self.poll_send()) {}
+        Ok(Async::Ready(()))
+    }
+
+    /// Try to send the next chunk of the body, or its trailers.
+    ///
+    /// Returns `true` once the entire body has been sent.
+    pub(crate) fn poll_send(&mut self) -> Poll<bool, FlushError> {
         use self::DataOrTrailers::*;
 
-        loop {
-            match try_ready!(self.poll_body()) {
-                Some(Data(buf)) => {
-                    let eos = self.body.is_end_stream();
+        match try_ready!(self.poll_body()) {
+            Some(Data(buf)) => {
+                let eos = self.body.is_end_stream();
 
-                    self.h2.send_data(SendBuf::new(buf), eos)?;
+                self.h2.send_data(SendBuf::new(buf), eos)?;
 
-                    if eos {
-                        self.state = FlushState::Done;
-                        return Ok(Async::Ready(()));
-                    }
-                }
-                Some(Trailers(trailers)) => {
-                    self.h2.send_trailers(trailers)?;
-                    return Ok(Async::Ready(()));
+                if eos {
+                    self.state = FlushState::Done;
                 }
-                None => {
-                    // If this is hit, then an EOS was not reached via the other
-                    // paths. So, we must send an empty data frame with EOS.
-                    self.h2.send_data(SendBuf::none(), true)?;
+                Ok(Async::Ready(eos))
+            }
+            Some(Trailers(trailers)) => {
+                self.h2.send_trailers(trailers)?;
+                Ok(Async::Ready(true))
+            }
+            None => {
+                // If this is hit, then an EOS was not reached via the other
+                // paths. So, we must send an empty data frame with EOS.
+                self.h2.send_data(SendBuf::none(), true)?;
 
-                    return Ok(Async::Ready(()));
-                }
+                Ok(Async::Ready(true))
             }
         }
     }
 
     /// Get the next message to write, either a data frame or trailers.
-    fn poll_body(&mut self) -> Poll<Option<DataOrTrailers<S::Data>>, h2::Error> {
+    fn poll_body(&mut self) -> Poll<Option<DataOrTrailers<S::Data>>, FlushError> {
         loop {
             match self.state {
                 FlushState::Data => {
@@ -92,10 +131,7 @@ where
                                 Some(_) => break,
                                 None => {
                                     debug!("connection closed early");
-                                    // The error shouldn't really matter at this
-                                    // point as the peer has disconnected, the
-                                    // error will be discarded anyway.
-                                    return Err(h2::Reason::INTERNAL_ERROR.into());
+                                    return Err(FlushError::Closed);
                                 }
                             }
                         }
@@ -106,7 +142,7 @@ where
                         match self.h2.poll_reset()? {
                             Async::Ready(reason) => {
                                 debug!("stream received RST_STREAM while flushing: {:?}", reason,);
-                                return Err(reason.into());
+                                return Err(FlushError::Reset(reason));
                             }
                             Async::NotReady => {
                                 // Stream hasn't been reset, so we can try
@@ -120,9 +156,7 @@ where
                     let item = try_ready!(self.body.poll_data().map_err(|err| {
                         let err = err.into();
                         debug!("user body error from poll_buf: {}", err);
-                        let reason = ::error::reason_from_dyn_error(&*err);
-                        self.h2.send_reset(reason);
-                        reason
+                        FlushError::Reset(self.reset_body_error(err))
                     }));
 
                     if let Some(data) = item {
@@ -140,7 +174,7 @@ where
                                 "stream received RST_STREAM while flushing trailers: {:?}",
                                 reason,
                             );
-                            return Err(reason.into());
+                            return Err(FlushError::Reset(reason));
                         }
                         Async::NotReady => {
                             // Stream hasn't been reset, so we can try
@@ -152,9 +186,7 @@ where
                     let trailers = try_ready!(self.body.poll_trailers().map_err(|err| {
                         let err = err.into();
                         debug!("user body error from poll_trailers: {}", err);
-                        let reason = ::error::reason_from_dyn_error(&*err);
-                        self.h2.send_reset(reason);
-                        reason
+                        FlushError::Reset(self.reset_body_error(err))
                     }));
                     self.state = FlushState::Done;
                     if let Some(trailers) = trailers {
@@ -165,6 +197,48 @@ where
             }
         }
     }
+
+    /// Reset the stream after the body failed with `err`.
+    fn reset_body_error(&mut self, err: Box<dyn std::error::Error>) -> h2::Reason {
+        let reason = self.reasons.reason(&*err);
+
+        if let Some(ref slot) = self.body_error {
+            *slot.lock().unwrap() = Some(BodyError::new(err, reason));
+        }
+
+        self.h2.send_reset(reason);
+        reason
+    }
+}
+
+// ===== impl FlushError =====
+
+impl FlushError {
+    /// Returns the reason the stream was reset with, or `None` if the
+    /// connection closed.
+    pub fn reason(&self) -> Option<h2::Reason> {
+        match *self {
+            FlushError::Reset(reason) => Some(reason),
+            FlushError::Closed => None,
+            FlushError::H2(ref err) => err.reason(),
+        }
+    }
+}
+
+impl From<h2::Error> for FlushError {
+    fn from(src: h2::Error) -> Self {
+        FlushError::H2(src)
+    }
+}
+
+impl fmt::Debug for FlushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FlushError::Reset(reason) => f.debug_tuple("Reset").field(&reason).finish(),
+            FlushError::Closed => f.write_str("Closed"),
+            FlushError::H2(ref err) => f.debug_tuple("H2").field(err).finish(),
+        }
+    }
 }
 
 impl<S> Future for Flush<S>
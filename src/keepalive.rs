@@ -0,0 +1,109 @@
+use timer::{self, Timer};
+
+use futures::Async;
+use h2::{Ping, PingPong};
+use tokio_timer::Delay;
+
+use std::time::Duration;
+
+/// Configures HTTP/2.0 PING keepalive for a connection.
+///
+/// Once every `interval`, a PING frame is sent to the peer. If the peer does
+/// not acknowledge it within `timeout`, the connection is considered dead and
+/// is closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeepAlive {
+    interval: Duration,
+    timeout: Duration,
+}
+
+/// Sends keepalive PINGs and watches for their PONGs.
+pub(crate) struct Pinger {
+    ping_pong: PingPong,
+    config: KeepAlive,
+    timer: Timer,
+    state: State,
+}
+
+enum State {
+    /// Waiting to send the next PING.
+    Idle(Delay),
+
+    /// A PING has been sent, and the PONG must arrive before the delay
+    /// elapses.
+    Pending(Delay),
+
+    /// The connection no longer supports PINGs, so keepalive has stopped.
+    Done,
+}
+
+// ===== impl KeepAlive =====
+
+impl KeepAlive {
+    /// Returns a `KeepAlive` that sends a PING every `interval` and waits up
+    /// to `timeout` for each to be acknowledged.
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        KeepAlive { interval, timeout }
+    }
+
+    /// Returns how long to wait between PINGs.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns how long to wait for a PING to be acknowledged.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+// ===== impl Pinger =====
+
+impl Pinger {
+    pub fn new(ping_pong: PingPong, config: KeepAlive, timer: Timer) -> Self {
+        let state = State::Idle(timer.delay(config.interval));
+
+        Pinger {
+            ping_pong,
+            config,
+            timer,
+            state,
+        }
+    }
+
+    /// Returns `true` if the peer has failed to acknowledge a PING in time.
+    ///
+    /// Otherwise, the current task is notified when there is more to do.
+    pub fn poll_expired(&mut self) -> bool {
+        loop {
+            self.state = match self.state {
+                State::Idle(ref mut delay) => {
+                    if !timer::poll_elapsed(delay) {
+                        return false;
+                    }
+
+                    trace!("sending keepalive PING");
+                    match self.ping_pong.send_ping(Ping::opaque()) {
+                        Ok(()) => State::Pending(self.timer.delay(self.config.timeout)),
+                        Err(e) => {
+                            debug!("failed to send keepalive PING: {}", e);
+                            State::Done
+                        }
+                    }
+                }
+                State::Pending(ref mut delay) => match self.ping_pong.poll_pong() {
+                    Ok(Async::Ready(_)) => {
+                        trace!("received keepalive PONG");
+                        State::Idle(self.timer.delay(self.config.interval))
+                    }
+                    Ok(Async::NotReady) => return timer::poll_elapsed(delay),
+                    Err(e) => {
+                        debug!("failed to receive keepalive PONG: {}", e);
+                        State::Done
+                    }
+                },
+                State::Done => return false,
+            };
+        }
+    }
+}
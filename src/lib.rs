@@ -7,6 +7,7 @@ extern crate http;
 extern crate log;
 extern crate tokio_connect;
 extern crate tokio_io;
+extern crate tokio_timer;
 extern crate tower_http;
 extern crate tower_service;
 extern crate tower;
@@ -18,10 +19,15 @@ mod body;
 mod buf;
 mod error;
 mod flush;
+mod keepalive;
 mod recv_body;
+mod timer;
 
 pub use h2::{Error, Reason};
 pub use body::NoBody;
+pub use error::{BodyError, ResetReason, ResetReasons};
+pub use keepalive::KeepAlive;
 pub use recv_body::{RecvBody, Data};
 pub use server::Server;
+pub use timer::Timer;
 pub use tower_http::{Body, HttpService};
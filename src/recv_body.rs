@@ -1,5 +1,6 @@
 use bytes::{Buf, Bytes, BytesMut};
-use futures::{Poll, Stream};
+use client::{CancelFlush, InFlight};
+use futures::{Async, Poll, Stream};
 use h2;
 use http;
 use Body;
@@ -8,6 +9,13 @@ use Body;
 #[derive(Debug)]
 pub struct RecvBody {
     inner: h2::RecvStream,
+
+    /// Keeps a client response counted as in flight until it is received.
+    in_flight: Option<InFlight>,
+
+    /// Stops the client request's body from being sent if the response is
+    /// dropped before it has been received.
+    cancel_flush: Option<CancelFlush>,
 }
 
 #[derive(Debug)]
@@ -20,7 +28,36 @@ pub struct Data {
 impl RecvBody {
     /// Return a new `RecvBody`.
     pub(crate) fn new(inner: h2::RecvStream) -> Self {
-        RecvBody { inner }
+        RecvBody {
+            inner,
+            in_flight: None,
+            cancel_flush: None,
+        }
+    }
+
+    /// Return a new `RecvBody` for a client response.
+    ///
+    /// Until the body has been received, it counts as in flight on the
+    /// connection, and dropping it stops the request body from being sent.
+    /// Dropping the body early releases the stream, so it is reset with
+    /// CANCEL and its unread data is returned to the connection's flow
+    /// control window.
+    pub(crate) fn client(
+        inner: h2::RecvStream,
+        in_flight: Option<InFlight>,
+        cancel_flush: Option<CancelFlush>,
+    ) -> Self {
+        let mut body = RecvBody {
+            inner,
+            in_flight,
+            cancel_flush,
+        };
+
+        if body.inner.is_end_stream() {
+            body.received();
+        }
+
+        body
     }
 
     /// Returns the stream ID of the received stream, or `None` if this body
@@ -28,6 +65,14 @@ impl RecvBody {
     pub fn stream_id(&self) -> h2::StreamId {
         self.inner.stream_id()
     }
+
+    /// Note that the whole body has been received.
+    fn received(&mut self) {
+        self.in_flight = None;
+        if let Some(cancel) = self.cancel_flush.take() {
+            cancel.disarm();
+        }
+    }
 }
 
 impl Body for RecvBody {
@@ -47,11 +92,22 @@ impl Body for RecvBody {
             Data { bytes }
         });
 
+        if data.is_none() && self.inner.is_end_stream() {
+            self.received();
+        }
+
         Ok(data.into())
     }
 
     fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
-        self.inner.poll_trailers()
+        let trailers = self.inner.poll_trailers();
+
+        match trailers {
+            Ok(Async::NotReady) => {}
+            _ => self.received(),
+        }
+
+        trailers
     }
 }
 
@@ -0,0 +1,70 @@
+use super::Modify;
+use RecvBody;
+
+use futures::future::{self, FutureResult};
+use futures::Future;
+use h2;
+use http::{Request, Response};
+
+use std::fmt;
+
+/// Decides whether each received request is dispatched to the service.
+///
+/// An `Admit` sees the whole request, including the `RecvBody` and its stream
+/// ID, before the service does. Its future may modify the request, or reject
+/// it with a response or by resetting the stream, such as after validating
+/// its headers or checking its authority. Other requests on the connection
+/// continue to be received while the future is pending.
+///
+/// Every `Modify` is an `Admit` that admits all requests.
+pub trait Admit<B> {
+    /// Resolves with the request to dispatch, or fails with how to reject it.
+    type Future: Future<Item = Request<RecvBody>, Error = Reject<B>>;
+
+    /// Start deciding whether to admit `request`.
+    fn admit(&mut self, request: Request<RecvBody>) -> Self::Future;
+}
+
+/// How an `Admit` rejects a request.
+pub enum Reject<B> {
+    /// Send this response instead of dispatching the request.
+    Respond(Response<B>),
+
+    /// Reset the request's stream with this reason.
+    Reset(h2::Reason),
+}
+
+// ===== impl Admit =====
+
+impl<B, M> Admit<B> for M
+where
+    M: Modify,
+{
+    type Future = FutureResult<Request<RecvBody>, Reject<B>>;
+
+    fn admit(&mut self, request: Request<RecvBody>) -> Self::Future {
+        let (parts, body) = request.into_parts();
+
+        // This is really unfortunate, but the `http` currently lacks the
+        // APIs to do this better :(
+        let mut request = Request::from_parts(parts, ());
+        self.modify(&mut request);
+
+        let (parts, _) = request.into_parts();
+        future::ok(Request::from_parts(parts, body))
+    }
+}
+
+// ===== impl Reject =====
+
+impl<B> fmt::Debug for Reject<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reject::Respond(ref response) => f
+                .debug_tuple("Respond")
+                .field(&response.status())
+                .finish(),
+            Reject::Reset(ref reason) => f.debug_tuple("Reset").field(reason).finish(),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use futures::future::Shared;
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+use h2;
+
+use std::fmt;
+
+/// Resolves with the `Reason` once a request's stream has been reset.
+///
+/// A `CancelToken` is inserted into the extensions of every request a server
+/// dispatches to its service, so that handlers can stop work on behalf of a
+/// request that will never be answered. The stream is reset when the client
+/// sends a `RST_STREAM`, in which case the token resolves with the client's
+/// reason, or when the server abandons the stream, such as when its deadline
+/// passes or its connection is forcibly closed, in which case it resolves
+/// with `CANCEL`. If the response body fails, the token resolves with the
+/// reason the stream was reset with.
+///
+/// If the stream completes without being reset, the token never resolves.
+#[derive(Clone)]
+pub struct CancelToken(Shared<oneshot::Receiver<h2::Reason>>);
+
+/// Held by a stream's `Background` task to resolve its `CancelToken`.
+pub(crate) struct CancelTrigger(Option<oneshot::Sender<h2::Reason>>);
+
+pub(crate) fn cancel_token() -> (CancelTrigger, CancelToken) {
+    let (tx, rx) = oneshot::channel();
+    (CancelTrigger(Some(tx)), CancelToken(rx.shared()))
+}
+
+// ===== impl CancelToken =====
+
+impl CancelToken {
+    /// Returns the reason the stream was reset, if it has been.
+    pub fn reason(&self) -> Option<h2::Reason> {
+        match self.0.peek() {
+            Some(Ok(reason)) => Some(*reason),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the stream has been reset.
+    pub fn is_canceled(&self) -> bool {
+        self.reason().is_some()
+    }
+}
+
+impl Future for CancelToken {
+    type Item = h2::Reason;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<h2::Reason, ()> {
+        match self.0.poll() {
+            Ok(Async::Ready(reason)) => Ok(Async::Ready(*reason)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // The stream completed without being reset.
+            Err(_) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("reason", &self.reason())
+            .finish()
+    }
+}
+
+// ===== impl CancelTrigger =====
+
+impl CancelTrigger {
+    /// Resolve the stream's `CancelToken` with `reason`.
+    pub fn cancel(&mut self, reason: h2::Reason) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(reason);
+        }
+    }
+}
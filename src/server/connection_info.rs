@@ -0,0 +1,86 @@
+use std::net::SocketAddr;
+
+/// Describes a connection accepted by a server.
+///
+/// A `ConnectionInfo` may be set as a connection's target with
+/// `ServeOptions::target`, so that the `MakeService` can build a service for
+/// the particular peer, and so that it can be inserted, as an
+/// `Arc<ConnectionInfo>`, into the extensions of every request on the
+/// connection.
+/// Every field is optional, since which of them are known depends on the
+/// transport the connection was accepted on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    alpn_protocol: Option<Vec<u8>>,
+    server_name: Option<String>,
+    peer_certificates: Vec<Vec<u8>>,
+}
+
+// ===== impl ConnectionInfo =====
+
+impl ConnectionInfo {
+    /// Returns a new `ConnectionInfo` that describes nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the address of the client.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Sets the address of the client.
+    pub fn set_peer_addr(&mut self, addr: SocketAddr) -> &mut Self {
+        self.peer_addr = Some(addr);
+        self
+    }
+
+    /// Returns the address the connection was accepted on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Sets the address the connection was accepted on.
+    pub fn set_local_addr(&mut self, addr: SocketAddr) -> &mut Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Returns the protocol negotiated with TLS ALPN, such as `b"h2"`.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_ref().map(|p| &p[..])
+    }
+
+    /// Sets the protocol negotiated with TLS ALPN.
+    pub fn set_alpn_protocol(&mut self, protocol: Vec<u8>) -> &mut Self {
+        self.alpn_protocol = Some(protocol);
+        self
+    }
+
+    /// Returns the server name the client requested with TLS SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_ref().map(|n| &n[..])
+    }
+
+    /// Sets the server name the client requested with TLS SNI.
+    pub fn set_server_name(&mut self, name: String) -> &mut Self {
+        self.server_name = Some(name);
+        self
+    }
+
+    /// Returns the DER-encoded certificate chain the client presented, with
+    /// the client's own certificate first.
+    ///
+    /// This is empty if the client did not authenticate.
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Sets the DER-encoded certificate chain the client presented.
+    pub fn set_peer_certificates(&mut self, certificates: Vec<Vec<u8>>) -> &mut Self {
+        self.peer_certificates = certificates;
+        self
+    }
+}
@@ -0,0 +1,161 @@
+use buf::SendBuf;
+use timer::{self, Timer};
+
+use bytes::Buf;
+use h2;
+use h2::server::SendResponse;
+use http::header::{HeaderName, HeaderValue};
+use http::{Request, Response};
+use tokio_timer::Delay;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Limits how long a server waits for its service to respond to a request.
+///
+/// The deadline is either a fixed duration, or derived from a request header
+/// such as gRPC's `grpc-timeout`. Once it passes, the service's response
+/// future is dropped and the stream is reset with `CANCEL`, unless a timeout
+/// response has been set, in which case that response is sent instead.
+#[derive(Clone)]
+pub struct Deadline {
+    source: Source,
+    response: Option<TimeoutResponse>,
+}
+
+type TimeoutResponse = Arc<dyn Fn() -> Response<()> + Send + Sync>;
+
+#[derive(Clone, Debug)]
+enum Source {
+    Fixed(Duration),
+    Header {
+        name: HeaderName,
+        parse: fn(&HeaderValue) -> Option<Duration>,
+    },
+}
+
+/// The deadline of a single request.
+pub(crate) struct Expiry {
+    delay: Delay,
+    response: Option<TimeoutResponse>,
+}
+
+// ===== impl Deadline =====
+
+impl Deadline {
+    /// Returns a `Deadline` that allows every request `timeout` to be
+    /// responded to.
+    pub fn fixed(timeout: Duration) -> Self {
+        Deadline {
+            source: Source::Fixed(timeout),
+            response: None,
+        }
+    }
+
+    /// Returns a `Deadline` read from each request's `name` header with
+    /// `parse`.
+    ///
+    /// Requests without the header, or for which `parse` returns `None`, have
+    /// no deadline.
+    pub fn from_header(name: HeaderName, parse: fn(&HeaderValue) -> Option<Duration>) -> Self {
+        Deadline {
+            source: Source::Header { name, parse },
+            response: None,
+        }
+    }
+
+    /// Returns a `Deadline` read from each request's `grpc-timeout` header.
+    pub fn grpc_timeout() -> Self {
+        Deadline::from_header(HeaderName::from_static("grpc-timeout"), parse_grpc_timeout)
+    }
+
+    /// Sets a response to send, with no body, in place of resetting streams
+    /// whose deadline has passed.
+    ///
+    /// For example, a gRPC server may respond with a `grpc-status` of
+    /// `DEADLINE_EXCEEDED`.
+    pub fn set_timeout_response<F>(&mut self, response: F)
+    where
+        F: Fn() -> Response<()> + Send + Sync + 'static,
+    {
+        self.response = Some(Arc::new(response));
+    }
+
+    /// Start the deadline for `request`, if it has one.
+    ///
+    /// A deadline too far in the future to be represented is no deadline at
+    /// all, since timeouts read from headers are chosen by the client.
+    pub(crate) fn start<T>(&self, request: &Request<T>, timer: &Timer) -> Option<Expiry> {
+        let timeout = match self.source {
+            Source::Fixed(timeout) => timeout,
+            Source::Header { ref name, parse } => request.headers().get(name).and_then(parse)?,
+        };
+
+        Some(Expiry {
+            delay: timer.checked_delay(timeout)?,
+            response: self.response.clone(),
+        })
+    }
+}
+
+impl fmt::Debug for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Deadline")
+            .field("source", &self.source)
+            .field("response", &self.response.is_some())
+            .finish()
+    }
+}
+
+// ===== impl Expiry =====
+
+impl Expiry {
+    /// Returns `true` once the deadline has passed.
+    pub fn poll_expired(&mut self) -> bool {
+        timer::poll_elapsed(&mut self.delay)
+    }
+
+    /// Give up on the response, sending the timeout response if there is
+    /// one, or resetting the stream otherwise.
+    pub fn respond<B: Buf>(&self, respond: &mut SendResponse<SendBuf<B>>) {
+        match self.response {
+            Some(ref response) => {
+                if let Err(e) = respond.send_response(response(), true) {
+                    debug!("error sending timeout response: {:?}", e);
+                }
+            }
+            None => respond.send_reset(h2::Reason::CANCEL),
+        }
+    }
+}
+
+/// Parses a gRPC timeout: up to 8 digits followed by a unit, one of `H`,
+/// `M`, `S`, `m`, `u`, or `n`.
+///
+/// Returns `None` if `value` is not a valid timeout. This is the parser used
+/// by `Deadline::grpc_timeout`.
+pub fn parse_grpc_timeout(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?;
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+
+    let timeout = match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+
+    Some(timeout)
+}
@@ -0,0 +1,65 @@
+use super::shutdown::Signal;
+use timer::{self, Timer};
+
+use tokio_timer::Delay;
+
+use std::time::{Duration, Instant};
+
+/// Tracks how long a connection has been idle.
+///
+/// A connection is idle while it has no streams in flight and has not
+/// received any requests. Other frames, such as the acknowledgements of
+/// keepalive PINGs, are not activity.
+pub(crate) struct Idle {
+    timeout: Duration,
+    delay: Delay,
+    last_activity: Instant,
+}
+
+// ===== impl Idle =====
+
+impl Idle {
+    pub fn new(timeout: Duration, timer: &Timer) -> Self {
+        Idle {
+            timeout,
+            delay: timer.delay(timeout),
+            last_activity: timer.now(),
+        }
+    }
+
+    /// Note that a request has been received, even if it was refused.
+    pub fn request_received(&mut self, timer: &Timer) {
+        self.last_activity = timer.now();
+    }
+
+    /// Note any streams that have been in flight since the last call.
+    pub fn record_activity(&mut self, signal: &Signal, timer: &Timer) {
+        if signal.take_emptied() || signal.in_flight() > 0 {
+            self.last_activity = timer.now();
+        }
+    }
+
+    /// Returns `true` once the connection has been idle for the timeout.
+    pub fn poll_expired(&mut self, signal: &Signal, timer: &Timer) -> bool {
+        loop {
+            if !timer::poll_elapsed(&mut self.delay) {
+                return false;
+            }
+
+            let now = timer.now();
+            let deadline = if signal.in_flight() > 0 {
+                now + self.timeout
+            } else {
+                self.last_activity + self.timeout
+            };
+
+            if deadline <= now {
+                return true;
+            }
+
+            // There has been activity since the timer was set, so push the
+            // deadline back and poll again to register interest.
+            self.delay.reset(deadline);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Reports the requests in flight on a `Server`, and those it has refused
+/// because of its in-flight limits.
+///
+/// Counts are shared by every connection served by the server, including
+/// connections served by its clones.
+#[derive(Clone)]
+pub struct LoadShed {
+    load: Arc<Load>,
+}
+
+/// Counts shared by all of a `Server`'s connections.
+pub(crate) struct Load {
+    in_flight: AtomicUsize,
+    connection_limited: AtomicUsize,
+    server_limited: AtomicUsize,
+}
+
+/// Held by each `Background` task while its stream counts towards the
+/// server-wide limit.
+pub(crate) struct Permit {
+    load: Arc<Load>,
+}
+
+// ===== impl LoadShed =====
+
+impl LoadShed {
+    pub(crate) fn new(load: Arc<Load>) -> Self {
+        LoadShed { load }
+    }
+
+    /// Returns the number of requests in flight on all of the server's
+    /// connections.
+    pub fn in_flight(&self) -> usize {
+        self.load.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of requests refused because their connection
+    /// already had as many requests in flight as it allows.
+    pub fn connection_limited(&self) -> usize {
+        self.load.connection_limited.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of requests refused because the server already had
+    /// as many requests in flight as it allows.
+    pub fn server_limited(&self) -> usize {
+        self.load.server_limited.load(Ordering::SeqCst)
+    }
+
+    /// Returns the total number of requests refused.
+    pub fn shed(&self) -> usize {
+        self.connection_limited() + self.server_limited()
+    }
+}
+
+impl fmt::Debug for LoadShed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LoadShed")
+            .field("in_flight", &self.in_flight())
+            .field("connection_limited", &self.connection_limited())
+            .field("server_limited", &self.server_limited())
+            .finish()
+    }
+}
+
+// ===== impl Load =====
+
+impl Load {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Load {
+            in_flight: AtomicUsize::new(0),
+            connection_limited: AtomicUsize::new(0),
+            server_limited: AtomicUsize::new(0),
+        })
+    }
+
+    /// Record a request refused by a connection's limit.
+    pub fn shed_by_connection(&self) {
+        self.connection_limited.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Admit a request, unless `max` requests are already in flight, in which
+    /// case it is recorded as refused by the server's limit.
+    ///
+    /// The count is only incremented while it is below `max`, so concurrent
+    /// calls are never refused because of each other's attempts.
+    pub fn acquire(this: &Arc<Self>, max: Option<usize>) -> Option<Permit> {
+        let max = match max {
+            Some(max) => max,
+            None => {
+                this.in_flight.fetch_add(1, Ordering::SeqCst);
+                return Some(Permit { load: this.clone() });
+            }
+        };
+
+        let mut prev = this.in_flight.load(Ordering::SeqCst);
+        loop {
+            if prev >= max {
+                this.server_limited.fetch_add(1, Ordering::SeqCst);
+                return None;
+            }
+
+            match this.in_flight.compare_exchange_weak(
+                prev,
+                prev + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Some(Permit { load: this.clone() }),
+                Err(actual) => prev = actual,
+            }
+        }
+    }
+}
+
+// ===== impl Permit =====
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.load.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -1,53 +1,150 @@
 use buf::SendBuf;
-use {flush, Body, RecvBody};
+use keepalive::Pinger;
+use timer::{self, Timer};
+use {flush, Body, KeepAlive, RecvBody, ResetReasons};
 
 use tower::MakeService;
 use tower_service::Service;
 
 use futures::future::{Either, Executor, Join, MapErr};
-use futures::{Async, Future, Poll, Stream};
+use futures::stream::FuturesUnordered;
+use futures::{task, Async, Future, Poll, Stream};
 use h2;
 use h2::server::{Connection as Accept, Handshake, SendResponse};
-use http::{Request, Response};
+use http::{Extensions, Request, Response};
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
 
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{error, fmt, mem};
 
+mod admit;
+mod cancel_token;
+mod connection_info;
+mod deadline;
+mod idle;
+mod limit;
+mod on_flush;
+mod serve_options;
+mod settings;
+mod shutdown;
+
+pub use self::admit::{Admit, Reject};
+pub use self::cancel_token::CancelToken;
+pub use self::connection_info::ConnectionInfo;
+pub use self::deadline::{parse_grpc_timeout, Deadline};
+pub use self::limit::LoadShed;
+pub use self::on_flush::{FlushOutcome, OnFlush};
+pub use self::serve_options::ServeOptions;
+pub use self::settings::ServerSettings;
+pub use self::shutdown::{DrainHandle, Drained, ShutdownHandle};
+
+use self::cancel_token::CancelTrigger;
+use self::deadline::Expiry;
+use self::idle::Idle;
+use self::limit::{Load, Permit};
+use self::shutdown::{Active, Cancel, Canceled, InFlight, Registry, Signal};
+
+/// How many services in a row a connection obtains to replace failed ones
+/// before giving up, if none of them is dispatched a request, unless set with
+/// `Server::set_max_recreates`.
+///
+/// A few attempts ride out a failure that a new service recovers from, while a
+/// `MakeService` whose services always fail does not keep the connection open
+/// indefinitely, refusing every stream.
+const DEFAULT_MAX_RECREATES: usize = 3;
+
 /// Attaches service implementations to h2 connections.
-pub struct Server<S, E, B>
+pub struct Server<S, E, B, A = ()>
 where
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
     B: Body,
 {
     new_service: S,
     builder: h2::server::Builder,
+    settings: ServerSettings,
     executor: E,
-    _p: PhantomData<B>,
+    registry: Arc<Registry>,
+    config: Arc<Config<B>>,
+    recreate: Option<RecreateService<S, A>>,
+    insert_target: Option<InsertTarget<A>>,
+    _p: PhantomData<A>,
 }
 
 /// Drives connection-level I/O .
-pub struct Connection<T, S, E, B, F>
+pub struct Connection<T, S, E, B, F, A = ()>
 where
     T: AsyncRead + AsyncWrite,
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
     B: Body,
+    F: Admit<B>,
 {
-    state: State<T, S, B>,
+    state: State<T, S, B, A>,
     executor: E,
-    modify: F,
+    admit: F,
+    admitting: FuturesUnordered<Admitting<F::Future, B>>,
+    signal: Arc<Signal>,
+    active: Option<Active>,
+    config: Arc<Config<B>>,
+    drain: Option<Delay>,
+    idle: Option<Idle>,
+    pinger: Option<Pinger>,
+    recreate: Option<RecreateService<S, A>>,
+    /// The number of services obtained since a request was last dispatched.
+    recreated: usize,
+    target: Arc<A>,
+    insert_target: Option<InsertTarget<A>>,
+    cancel: Option<Cancel>,
+    canceled: Canceled,
 }
 
 /// Modify a received request
+///
+/// To reject requests, or to inspect their bodies, use an `Admit` instead.
 pub trait Modify {
     /// Modify a request before calling the service.
     fn modify(&mut self, request: &mut Request<()>);
 }
 
-enum State<T, S, B>
+/// Maps service errors into responses.
+pub trait ErrorResponder<B> {
+    /// Returns the response to send in place of `error`, or `None` to reset
+    /// the stream.
+    fn respond(&self, error: &(dyn error::Error + 'static)) -> Option<Response<B>>;
+}
+
+type SharedErrorResponder<B> = Arc<dyn ErrorResponder<B> + Send + Sync>;
+
+/// Server connection configuration that is not handled by h2 itself.
+///
+/// Shared by a `Server`'s connections and the tasks responding on them.
+pub(crate) struct Config<B> {
+    pub timer: Timer,
+    pub drain_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub keepalive: Option<KeepAlive>,
+    pub deadline: Option<Deadline>,
+    pub error_responder: Option<SharedErrorResponder<B>>,
+    pub reset_reasons: Arc<ResetReasons>,
+    pub max_in_flight_per_connection: Option<usize>,
+    pub max_in_flight: Option<usize>,
+    pub load: Arc<Load>,
+    pub max_recreates: usize,
+}
+
+/// Obtains a new service for a connection whose service has failed.
+type RecreateService<S, A> =
+    Arc<dyn Fn(&A) -> <S as MakeService<A, Request<RecvBody>>>::Future + Send + Sync>;
+
+/// Inserts a connection's target into a request's extensions.
+type InsertTarget<A> = fn(&Arc<A>, &mut Extensions);
+
+enum State<T, S, B, A>
 where
     T: AsyncRead + AsyncWrite,
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
     B: Body,
 {
     /// Establish the HTTP/2.0 connection and get a service to process inbound
@@ -60,10 +157,24 @@ where
         service: S::Service,
     },
 
-    /// The service has closed, so poll until connection is closed.
+    /// The service has failed, so a new one is being obtained. Streams are
+    /// refused in the meantime.
+    Recreate {
+        connection: Accept<T, SendBuf<B::Data>>,
+        future: S::Future,
+    },
+
+    /// The service has closed or the connection is being forcibly closed, so
+    /// poll until the connection is closed.
     GoAway {
         connection: Accept<T, SendBuf<B::Data>>,
-        error: Error<S>,
+        error: Error<S, A>,
+        /// Set when the connection is being forcibly closed, until it has
+        /// been shut down abruptly.
+        abort: bool,
+        /// Set when the client is unresponsive, since it may never read the
+        /// frames queued for it. Once it elapses, the connection is dropped.
+        give_up: Option<Delay>,
     },
 
     /// Everything is closed up.
@@ -75,12 +186,45 @@ type Init<T, B, S, E> = Join<MapErr<Handshake<T, B>, MapErrA<E>>, MapErr<S, MapE
 type MapErrA<E> = fn(h2::Error) -> Either<h2::Error, E>;
 type MapErrB<E> = fn(E) -> Either<h2::Error, E>;
 
+/// A received request that is waiting to be admitted.
+///
+/// Resolves with the request's stream once the request has been admitted or
+/// rejected, or fails if the client resets the stream, or its deadline
+/// passes, first.
+struct Admitting<F, B>
+where
+    B: Body,
+{
+    future: F,
+    stream: Option<Accepted<B>>,
+}
+
+/// The stream of a received request, and the guards it holds while it is in
+/// flight.
+struct Accepted<B>
+where
+    B: Body,
+{
+    respond: SendResponse<SendBuf<B::Data>>,
+    deadline: Option<Expiry>,
+    reset: CancelTrigger,
+    in_flight: InFlight,
+    permit: Permit,
+}
+
 /// Task used to process requests
 pub struct Background<T, B>
 where
     B: Body,
 {
     state: BackgroundState<T, B>,
+    deadline: Option<Expiry>,
+    config: Arc<Config<B>>,
+    reset: CancelTrigger,
+    on_flush: Option<OnFlush>,
+    canceled: Canceled,
+    _in_flight: InFlight,
+    _permit: Permit,
 }
 
 enum BackgroundState<T, B>
@@ -91,13 +235,17 @@ where
         respond: SendResponse<SendBuf<B::Data>>,
         response: T,
     },
+    Reject {
+        respond: SendResponse<SendBuf<B::Data>>,
+        response: Option<Response<B>>,
+    },
     Flush(flush::Flush<B>),
 }
 
 /// Error produced by a `Connection`.
-pub enum Error<S>
+pub enum Error<S, A = ()>
 where
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
 {
     /// Error produced during the HTTP/2.0 handshake.
     Handshake(h2::Error),
@@ -113,6 +261,14 @@ where
 
     /// Error produced when attempting to spawn a task
     Execute,
+
+    /// A graceful shutdown did not complete before the drain timeout, so the
+    /// connection was forcibly closed.
+    DrainTimeout,
+
+    /// The client did not acknowledge a keepalive PING in time, so the
+    /// connection was forcibly closed.
+    KeepAliveTimeout,
 }
 
 enum PollMain {
@@ -122,9 +278,9 @@ enum PollMain {
 
 // ===== impl Server =====
 
-impl<S, E, B> Server<S, E, B>
+impl<S, E, B, A> Server<S, E, B, A>
 where
-    S: MakeService<(), Request<RecvBody>, Response = Response<B>>,
+    S: MakeService<A, Request<RecvBody>, Response = Response<B>>,
     S::Error: Into<Box<dyn std::error::Error>>,
     B: Body + 'static,
     B::Error: Into<Box<dyn std::error::Error>>,
@@ -135,59 +291,302 @@ where
             new_service,
             executor,
             builder,
+            settings: ServerSettings::default(),
+            registry: Registry::new(),
+            config: Arc::new(Config::new()),
+            recreate: None,
+            insert_target: None,
             _p: PhantomData,
         }
     }
 }
 
-impl<S, E, B> Server<S, E, B>
+impl<S, E, B, A> Server<S, E, B, A>
 where
-    S: MakeService<(), Request<RecvBody>, Response = Response<B>>,
+    S: MakeService<A, Request<RecvBody>, Response = Response<B>>,
     B: Body,
     B::Data: 'static,
     B::Error: Into<Box<dyn std::error::Error>>,
     E: Clone,
 {
-    /// Produces a future that is satisfied once the h2 connection has been initialized.
-    pub fn serve<T>(&mut self, io: T) -> Connection<T, S, E, B, ()>
+    /// Returns the initial SETTINGS advertised on new connections.
+    pub fn settings(&self) -> &ServerSettings {
+        &self.settings
+    }
+
+    /// Sets the initial SETTINGS advertised on new connections.
+    ///
+    /// These settings are applied on top of the `h2::server::Builder` the
+    /// server was created with, and may be overridden for an individual
+    /// connection with `ServeOptions::settings`.
+    pub fn set_settings(&mut self, settings: ServerSettings) {
+        self.settings = settings;
+    }
+
+    /// Sets the `Timer` used to drive this server's timeouts.
+    pub fn set_timer(&mut self, timer: Timer) {
+        self.config_mut().timer = timer;
+    }
+
+    /// Sets how long a connection may take to drain after a graceful shutdown
+    /// has started.
+    ///
+    /// Once the timeout elapses, any streams that are still in flight are
+    /// reset with `CANCEL`, the connection is closed, and the `Connection`
+    /// future fails with `Error::DrainTimeout`. By default, connections may
+    /// drain indefinitely.
+    pub fn set_drain_timeout(&mut self, timeout: Option<Duration>) {
+        self.config_mut().drain_timeout = timeout;
+    }
+
+    /// Sets how long a connection may remain idle before it is closed.
+    ///
+    /// A connection is idle while it has no streams in flight and receives
+    /// no requests from the client. Other frames, such as the acknowledgements
+    /// of keepalive PINGs, do not keep a connection from being idle. Once it
+    /// has been idle for the timeout, the connection sends a `GOAWAY` with
+    /// `NO_ERROR` and closes. By default, idle connections are kept open
+    /// indefinitely.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.config_mut().idle_timeout = timeout;
+    }
+
+    /// Sets the PING keepalive used by new connections.
+    ///
+    /// If the client fails to acknowledge a keepalive PING in time, its
+    /// in-flight streams are reset with `CANCEL`, the connection is closed,
+    /// and the `Connection` future fails with `Error::KeepAliveTimeout`. If
+    /// the client does not read the resets and `GOAWAY` within the keepalive
+    /// timeout, the connection is dropped without them. By default, no
+    /// keepalive PINGs are sent.
+    pub fn set_keepalive(&mut self, keepalive: Option<KeepAlive>) {
+        self.config_mut().keepalive = keepalive;
+    }
+
+    /// Sets the deadline by which the service must respond to each request
+    /// on new connections.
+    ///
+    /// A request's deadline starts when it is received, so time spent waiting
+    /// to be admitted, or for the service to become ready, counts against it.
+    /// Once it passes, the request is abandoned and its stream is reset with
+    /// `CANCEL`, or answered with the `Deadline`'s timeout response. By
+    /// default, the service may take indefinitely.
+    pub fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.config_mut().deadline = deadline;
+    }
+
+    /// Sets how service errors are mapped into responses.
+    ///
+    /// When `responder` returns `None` for an error, or if no responder is
+    /// set, the stream is reset with a reason derived from the error.
+    pub fn set_error_responder<R>(&mut self, responder: R)
     where
-        T: AsyncRead + AsyncWrite,
+        R: ErrorResponder<B> + Send + Sync + 'static,
     {
-        self.serve_modified(io, ())
+        self.config_mut().error_responder = Some(Arc::new(responder));
     }
 
-    pub fn serve_modified<T, F>(&mut self, io: T, modify: F) -> Connection<T, S, E, B, F>
+    /// Sets how service and response body errors are classified into the
+    /// reason their streams are reset with.
+    pub fn set_reset_reasons(&mut self, reasons: ResetReasons) {
+        self.config_mut().reset_reasons = Arc::new(reasons);
+    }
+
+    /// Sets how many requests each new connection may have in flight.
+    ///
+    /// Requests received while their connection is at the limit are refused
+    /// with `REFUSED_STREAM`, which tells the client that they were not
+    /// processed and may be retried. Requests are checked against the limit
+    /// even while the service is not ready for them. By default, there is no
+    /// limit.
+    pub fn set_max_in_flight_per_connection(&mut self, max: Option<usize>) {
+        self.config_mut().max_in_flight_per_connection = max;
+    }
+
+    /// Sets how many requests may be in flight across all connections served
+    /// by this server and its clones.
+    ///
+    /// Requests received while the server is at the limit are refused with
+    /// `REFUSED_STREAM`, as with `set_max_in_flight_per_connection`. By
+    /// default, there is no limit.
+    pub fn set_max_in_flight(&mut self, max: Option<usize>) {
+        self.config_mut().max_in_flight = max;
+    }
+
+    /// Sets whether a connection obtains a new service when its service fails.
+    ///
+    /// By default, when a connection's service fails, the connection sends a
+    /// `GOAWAY`, waits for its in-flight streams to complete, and then fails
+    /// with `Error::Service`. When enabled, the connection instead stays open
+    /// and obtains a new service from this server's `MakeService`, refusing
+    /// streams with `REFUSED_STREAM` until it is ready. If a new service
+    /// cannot be obtained, the connection fails with `Error::NewService`. If
+    /// the new services keep failing before any request is dispatched to
+    /// them, the connection gives up after `set_max_recreates` of them, and
+    /// fails with `Error::Service` as it would have without recreation.
+    pub fn set_recreate_on_service_error(&mut self, enabled: bool)
+    where
+        S: Clone + Send + Sync + 'static,
+        A: Clone,
+    {
+        self.recreate = if enabled {
+            let new_service = self.new_service.clone();
+            Some(Arc::new(move |target: &A| {
+                new_service.clone().make_service(target.clone())
+            }))
+        } else {
+            None
+        };
+    }
+
+    /// Sets how many services in a row a connection obtains to replace failed
+    /// ones, when recreation is enabled with `set_recreate_on_service_error`.
+    ///
+    /// The count starts over whenever a request is dispatched to a service.
+    /// Defaults to 3.
+    pub fn set_max_recreates(&mut self, max: usize) {
+        self.config_mut().max_recreates = max;
+    }
+
+    /// Sets whether the target set with `ServeOptions::target` is inserted
+    /// into the extensions of every request received on the connection.
+    ///
+    /// The target is inserted as an `Arc<A>`, shared by all of the
+    /// connection's requests, before the request is passed to `Admit`. By
+    /// default, it is only passed to the `MakeService`.
+    pub fn set_insert_target(&mut self, enabled: bool)
+    where
+        A: Send + Sync + 'static,
+    {
+        self.insert_target = if enabled {
+            Some(insert_target::<A>)
+        } else {
+            None
+        };
+    }
+
+    /// Returns a handle that reports the requests in flight on this server
+    /// and its clones, and how many have been refused.
+    pub fn load_shed(&self) -> LoadShed {
+        LoadShed::new(self.config.load.clone())
+    }
+
+    /// Returns a handle that gracefully shuts down every connection served by
+    /// this server, including connections served by its clones.
+    pub fn drain_handle(&self) -> DrainHandle {
+        DrainHandle::new(self.registry.clone())
+    }
+
+    /// Returns the configuration to modify, copying it first if connections
+    /// already share it, so that changes only apply to new connections.
+    fn config_mut(&mut self) -> &mut Config<B> {
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Produces a future that is satisfied once the h2 connection has been
+    /// initialized, serving it as described by `options`.
+    ///
+    /// `options` may set the target passed to the `MakeService`, an `Admit`
+    /// to pass requests through, and SETTINGS to advertise in place of the
+    /// server's.
+    pub fn serve_with<T, F>(
+        &mut self,
+        io: T,
+        options: ServeOptions<A, F>,
+    ) -> Connection<T, S, E, B, F, A>
     where
         T: AsyncRead + AsyncWrite,
-        F: Modify,
+        F: Admit<B>,
+        A: Clone,
     {
+        let ServeOptions {
+            target,
+            admit,
+            settings,
+        } = options;
+
         // Clone a handle to the executor so that it can be moved into the
         // connection handle
         let executor = self.executor.clone();
 
         let service = self
             .new_service
-            .make_service(())
+            .make_service(target.clone())
             .map_err(Either::B as MapErrB<S::MakeError>);
 
-        // TODO we should specify initial settings here!
-        let handshake = self
-            .builder
+        let mut builder = self.builder.clone();
+        self.settings.apply(&mut builder);
+        settings.apply(&mut builder);
+
+        let timer = &self.config.timer;
+        let idle = self
+            .config
+            .idle_timeout
+            .map(|timeout| Idle::new(timeout, timer));
+
+        let handshake = builder
             .handshake(io)
             .map_err(Either::A as MapErrA<S::MakeError>);
 
+        let signal = self.registry.register();
+        let active = Some(Active::new(signal.clone()));
+        let (cancel, canceled) = shutdown::cancel();
+
         Connection {
             state: State::Init(handshake.join(service)),
             executor,
-            modify,
+            admit,
+            admitting: FuturesUnordered::new(),
+            signal,
+            active,
+            config: self.config.clone(),
+            drain: None,
+            idle,
+            pinger: None,
+            recreate: self.recreate.clone(),
+            recreated: 0,
+            target: Arc::new(target),
+            insert_target: self.insert_target,
+            cancel: Some(cancel),
+            canceled,
         }
     }
 }
 
+impl<S, E, B> Server<S, E, B>
+where
+    S: MakeService<(), Request<RecvBody>, Response = Response<B>>,
+    B: Body,
+    B::Data: 'static,
+    B::Error: Into<Box<dyn std::error::Error>>,
+    E: Clone,
+{
+    /// Produces a future that is satisfied once the h2 connection has been initialized.
+    pub fn serve<T>(&mut self, io: T) -> Connection<T, S, E, B, ()>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        self.serve_modified(io, ())
+    }
+
+    /// Produces a future that is satisfied once the h2 connection has been
+    /// initialized, passing each request through `modify` before the service.
+    ///
+    /// `modify` may be a `Modify`, or an `Admit` to reject requests or to
+    /// decide on them asynchronously.
+    pub fn serve_modified<T, F>(&mut self, io: T, modify: F) -> Connection<T, S, E, B, F>
+    where
+        T: AsyncRead + AsyncWrite,
+        F: Admit<B>,
+    {
+        self.serve_with(io, ServeOptions::new().admit(modify))
+    }
+}
+
 // B doesn't need to be Clone, it's just a marker type.
-impl<S, E, B> Clone for Server<S, E, B>
+impl<S, E, B, A> Clone for Server<S, E, B, A>
 where
-    S: MakeService<(), Request<RecvBody>> + Clone,
+    S: MakeService<A, Request<RecvBody>> + Clone,
     E: Clone,
     B: Body,
 {
@@ -196,6 +595,11 @@ where
             new_service: self.new_service.clone(),
             executor: self.executor.clone(),
             builder: self.builder.clone(),
+            settings: self.settings.clone(),
+            registry: self.registry.clone(),
+            config: self.config.clone(),
+            recreate: self.recreate.clone(),
+            insert_target: self.insert_target,
             _p: PhantomData,
         }
     }
@@ -203,39 +607,75 @@ where
 
 // ===== impl Connection =====
 
-impl<T, S, E, B, F> Future for Connection<T, S, E, B, F>
+impl<T, S, E, B, F, A> Future for Connection<T, S, E, B, F, A>
 where
     T: AsyncRead + AsyncWrite,
-    S: MakeService<(), Request<RecvBody>, Response = Response<B>>,
+    S: MakeService<A, Request<RecvBody>, Response = Response<B>>,
     S::Error: Into<Box<dyn std::error::Error>>,
     E: Executor<Background<<S::Service as Service<Request<RecvBody>>>::Future, B>>,
     B: Body + 'static,
     B::Error: Into<Box<dyn std::error::Error>>,
-    F: Modify,
+    F: Admit<B>,
 {
     type Item = ();
-    type Error = Error<S>;
+    type Error = Error<S, A>;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Code is in poll2 to make sure any Err returned
-        // transitions state to State::Done.
-        self.poll2().map_err(|e| {
-            self.state = State::Done;
-            e
-        })
+        if self.signal.poll_shutdown() {
+            self.graceful_shutdown();
+        }
+
+        if self.poll_drain_timeout() {
+            debug!("drain timeout elapsed; forcibly closing connection");
+            self.force_close(Error::DrainTimeout, None);
+        }
+
+        if self.poll_keepalive_timeout() {
+            debug!("keepalive PING timed out; forcibly closing connection");
+            // Wait no longer for the client to read the resets and GOAWAY
+            // than it had to acknowledge the PING.
+            let give_up = self.config.keepalive.map(|keepalive| keepalive.timeout());
+            self.force_close(Error::KeepAliveTimeout, give_up);
+        }
+
+        let mut poll = self.poll_connection();
+
+        if let Ok(Async::NotReady) = poll {
+            if self.poll_idle_timeout() {
+                self.close_idle();
+                poll = self.poll_connection();
+            }
+        }
+
+        match poll {
+            Ok(Async::NotReady) => {}
+            _ => {
+                // The connection is complete, so it no longer keeps a drain
+                // from finishing.
+                self.active = None;
+            }
+        }
+
+        poll
     }
 }
 
-impl<T, S, E, B, F> Connection<T, S, E, B, F>
+impl<T, S, E, B, F, A> Connection<T, S, E, B, F, A>
 where
     T: AsyncRead + AsyncWrite,
-    S: MakeService<(), Request<RecvBody>, Response = Response<B>>,
+    S: MakeService<A, Request<RecvBody>, Response = Response<B>>,
     S::Error: Into<Box<dyn std::error::Error>>,
     E: Executor<Background<<S::Service as Service<Request<RecvBody>>>::Future, B>>,
     B: Body + 'static,
     B::Error: Into<Box<dyn std::error::Error>>,
-    F: Modify,
+    F: Admit<B>,
 {
+    /// Returns a handle that can start a graceful shutdown of this connection
+    /// after it has been moved onto an executor.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.signal.clone())
+    }
+
     /// Start an HTTP2 graceful shutdown.
     ///
     /// The `Connection` must continue to be polled until shutdown completes.
@@ -246,8 +686,19 @@ where
             }
             State::Ready {
                 ref mut connection, ..
+            }
+            | State::Recreate {
+                ref mut connection, ..
             } => {
                 connection.graceful_shutdown();
+
+                if self.drain.is_none() {
+                    let timer = &self.config.timer;
+                    self.drain = self
+                        .config
+                        .drain_timeout
+                        .map(|timeout| timer.delay(timeout));
+                }
                 return;
             }
             State::GoAway { .. } => return,
@@ -257,7 +708,111 @@ where
         self.state = State::Done;
     }
 
-    fn poll2(&mut self) -> Poll<(), Error<S>> {
+    /// Returns `true` once the drain timeout has elapsed after a graceful
+    /// shutdown was started.
+    fn poll_drain_timeout(&mut self) -> bool {
+        let expired = match self.drain {
+            Some(ref mut delay) => timer::poll_elapsed(delay),
+            None => false,
+        };
+
+        if expired {
+            self.drain = None;
+        }
+
+        expired
+    }
+
+    /// Returns `true` if the client failed to acknowledge a keepalive PING in
+    /// time.
+    fn poll_keepalive_timeout(&mut self) -> bool {
+        let expired = match self.pinger {
+            Some(ref mut pinger) => pinger.poll_expired(),
+            None => false,
+        };
+
+        if expired {
+            self.pinger = None;
+        }
+
+        expired
+    }
+
+    /// Returns `true` once the connection has been idle for the idle timeout.
+    fn poll_idle_timeout(&mut self) -> bool {
+        // Only established connections can be idle.
+        match self.state {
+            State::Ready { .. } => {}
+            _ => return false,
+        }
+
+        match self.idle {
+            Some(ref mut idle) => idle.poll_expired(&self.signal, &self.config.timer),
+            None => false,
+        }
+    }
+
+    /// Close an idle connection with a `GOAWAY`.
+    fn close_idle(&mut self) {
+        debug!("idle timeout elapsed; closing connection");
+
+        self.idle = None;
+
+        if let State::Ready {
+            ref mut connection, ..
+        } = self.state
+        {
+            connection.abrupt_shutdown(h2::Reason::NO_ERROR);
+        }
+    }
+
+    /// Reset all in-flight streams and close the connection without waiting
+    /// for them to complete.
+    ///
+    /// The connection is shut down once the streams' resets have been sent,
+    /// since shutting it down discards any frames that are still queued.
+    ///
+    /// Once the connection has closed, the `Connection` fails with `error`. If
+    /// `give_up` is set, the connection is dropped once it elapses, even if the
+    /// frames queued for the client have not been sent.
+    fn force_close(&mut self, error: Error<S, A>, give_up: Option<Duration>) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel();
+        }
+        self.admitting = FuturesUnordered::new();
+
+        let timer = &self.config.timer;
+        let give_up = give_up.map(|timeout| timer.delay(timeout));
+
+        self.state = match mem::replace(&mut self.state, State::Done) {
+            State::Ready { connection, .. }
+            | State::Recreate { connection, .. }
+            | State::GoAway { connection, .. } => State::GoAway {
+                connection,
+                error,
+                abort: true,
+                give_up,
+            },
+            state => state,
+        };
+    }
+
+    fn poll_connection(&mut self) -> Poll<(), Error<S, A>> {
+        // Code is in poll2 to make sure any Err returned
+        // transitions state to State::Done.
+        let poll = self.poll2().map_err(|e| {
+            self.state = State::Done;
+            e
+        });
+
+        if let Some(ref mut idle) = self.idle {
+            idle.record_activity(&self.signal, &self.config.timer);
+        }
+
+        poll
+    }
+
+    fn poll2(&mut self) -> Poll<(), Error<S, A>> {
         loop {
             match self.state {
                 State::Init(..) => try_ready!(self.poll_init()),
@@ -268,20 +823,28 @@ where
                         return Ok(().into());
                     }
                 },
+                State::Recreate { .. } => try_ready!(self.poll_recreate()),
                 State::GoAway { .. } => try_ready!(self.poll_goaway()),
                 State::Done => return Ok(().into()),
             }
         }
     }
 
-    fn poll_init(&mut self) -> Poll<(), Error<S>> {
+    fn poll_init(&mut self) -> Poll<(), Error<S, A>> {
         use self::State::*;
 
-        let (connection, service) = match self.state {
+        let (mut connection, service) = match self.state {
             Init(ref mut join) => try_ready!(join.poll().map_err(Error::from_init)),
             _ => unreachable!(),
         };
 
+        if let Some(keepalive) = self.config.keepalive {
+            let timer = &self.config.timer;
+            self.pinger = connection
+                .ping_pong()
+                .map(|ping_pong| Pinger::new(ping_pong, keepalive, timer.clone()));
+        }
+
         self.state = Ready {
             connection,
             service,
@@ -290,71 +853,200 @@ where
         Ok(().into())
     }
 
-    fn poll_main(&mut self) -> Poll<PollMain, Error<S>> {
+    fn poll_main(&mut self) -> Poll<PollMain, Error<S, A>> {
         let error = match self.state {
             State::Ready {
                 ref mut connection,
                 ref mut service,
-            } => loop {
+            } => 'accept: loop {
                 // Make sure the service is ready
-                match service.poll_ready() {
-                    Ok(Async::Ready(())) => (),
+                let ready = match service.poll_ready() {
+                    Ok(Async::Ready(())) => true,
                     Ok(Async::NotReady) => {
-                        // Just because the service isn't ready doesn't mean
-                        // we do nothing. We must keep polling the connection
-                        // regardless. However, since we don't want to accept
-                        // a request, we `poll_close` instead of `poll`.
-                        let next = connection.poll_close().map_err(Error::Protocol);
-
-                        // If not ready, we'll get polled again.
-                        try_ready!(next);
-
-                        // If poll_close was ready, that means the connection
-                        // is closed. All done!
-                        return Ok(PollMain::Done.into());
+                        // With an in-flight limit, streams are still accepted
+                        // while the service is not ready, so that those over
+                        // the limit are refused rather than left waiting. The
+                        // limit bounds how many wait to be dispatched.
+                        let limited = self.config.max_in_flight.is_some()
+                            || self.config.max_in_flight_per_connection.is_some();
+                        if !limited {
+                            // Just because the service isn't ready doesn't
+                            // mean we do nothing. We must keep polling the
+                            // connection regardless. However, since we don't
+                            // want to accept a request, we `poll_close`
+                            // instead of `poll`.
+                            let next = connection.poll_close().map_err(Error::Protocol);
+
+                            // If not ready, we'll get polled again.
+                            try_ready!(next);
+
+                            // If poll_close was ready, that means the
+                            // connection is closed. All done!
+                            return Ok(PollMain::Done.into());
+                        }
+                        false
                     }
                     Err(err) => {
                         trace!("service closed");
                         // service is closed, transition to goaway state
                         break Error::Service(err);
                     }
+                };
+
+                // Dispatch the first request to have been admitted, if any,
+                // once the service is ready.
+                let mut admitted = None;
+                while ready {
+                    match self.admitting.poll() {
+                        Ok(Async::Ready(Some((accepted, Ok(request))))) => {
+                            admitted = Some((accepted, request));
+                            break;
+                        }
+                        Ok(Async::Ready(Some((mut accepted, Err(reject))))) => match reject {
+                            Reject::Respond(response) => {
+                                debug!("request rejected; sending response");
+                                let background = Background::reject(
+                                    accepted,
+                                    response,
+                                    self.config.clone(),
+                                    self.canceled.clone(),
+                                );
+                                if let Err(_) = self.executor.execute(background) {
+                                    break 'accept Error::Execute;
+                                }
+                            }
+                            Reject::Reset(reason) => {
+                                debug!("request rejected; resetting stream");
+                                accepted.respond.send_reset(reason);
+                            }
+                        },
+                        // The stream was reset before its request was admitted.
+                        Err(()) => {}
+                        Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                    }
+                }
+
+                if let Some((admitted, request)) = admitted {
+                    // Dispatch the request to the service
+                    let response = service.call(request);
+                    self.recreated = 0;
+
+                    // Spawn a new task to process the response future
+                    let background = Background::new(
+                        admitted,
+                        response,
+                        self.config.clone(),
+                        self.canceled.clone(),
+                    );
+                    if let Err(_) = self.executor.execute(background) {
+                        break Error::Execute;
+                    }
+
+                    // The service must be ready again before another request
+                    // is dispatched to it.
+                    continue;
                 }
 
                 let next = connection.poll().map_err(Error::Protocol);
 
-                let (request, respond) = match try_ready!(next) {
+                let (request, mut respond) = match try_ready!(next) {
                     Some(next) => next,
                     None => return Ok(PollMain::Done.into()),
                 };
 
-                let (parts, body) = request.into_parts();
-
-                // This is really unfortunate, but the `http` currently lacks the
-                // APIs to do this better :(
-                let mut request = Request::from_parts(parts, ());
-                self.modify.modify(&mut request);
-
-                let (parts, _) = request.into_parts();
-                let request = Request::from_parts(parts, RecvBody::new(body));
+                if let Some(ref mut idle) = self.idle {
+                    idle.request_received(&self.config.timer);
+                }
 
-                // Dispatch the request to the service
-                let response = service.call(request);
+                // Refuse the request if it would exceed an in-flight limit,
+                // so that the client may retry it elsewhere.
+                let connection_in_flight = self.signal.in_flight();
+                let at_limit = self
+                    .config
+                    .max_in_flight_per_connection
+                    .map_or(false, |max| connection_in_flight >= max);
+                let permit = if at_limit {
+                    self.config.load.shed_by_connection();
+                    None
+                } else {
+                    Load::acquire(&self.config.load, self.config.max_in_flight)
+                };
+                let permit = match permit {
+                    Some(permit) => permit,
+                    None => {
+                        debug!("in-flight limit reached; refusing stream");
+                        respond.send_reset(h2::Reason::REFUSED_STREAM);
+                        continue;
+                    }
+                };
+                let in_flight = InFlight::new(self.signal.clone());
 
-                // Spawn a new task to process the response future
-                if let Err(_) = self.executor.execute(Background::new(respond, response)) {
-                    break Error::Execute;
+                let (parts, body) = request.into_parts();
+                let mut request = Request::from_parts(parts, RecvBody::new(body));
+                if let Some(insert_target) = self.insert_target {
+                    insert_target(&self.target, request.extensions_mut());
                 }
+
+                let (reset, token) = cancel_token::cancel_token();
+                request.extensions_mut().insert(token);
+
+                // The deadline includes the time spent waiting to be admitted
+                // and dispatched.
+                let timer = &self.config.timer;
+                let deadline = self
+                    .config
+                    .deadline
+                    .as_ref()
+                    .and_then(|deadline| deadline.start(&request, timer));
+
+                // The request is dispatched once it has been admitted.
+                let future = self.admit.admit(request);
+                self.admitting.push(Admitting {
+                    future,
+                    stream: Some(Accepted {
+                        respond,
+                        deadline,
+                        reset,
+                        in_flight,
+                        permit,
+                    }),
+                });
             },
             _ => unreachable!(),
         };
 
         // We only break out of the loop on an error, which means we
-        // should transition to GOAWAY.
+        // should transition to GOAWAY, unless a failed service is to be
+        // replaced.
         match mem::replace(&mut self.state, State::Done) {
             State::Ready { mut connection, .. } => {
+                let recreate = if self.recreated < self.config.max_recreates {
+                    self.recreate.as_ref()
+                } else {
+                    None
+                };
+                if let (&Error::Service(_), Some(recreate)) = (&error, recreate) {
+                    // Requests that are still waiting to be admitted will be
+                    // dispatched to the new service.
+                    debug!("service failed; obtaining a new service");
+                    self.recreated += 1;
+                    let future = recreate(&self.target);
+                    self.state = State::Recreate { connection, future };
+
+                    return Ok(Async::Ready(PollMain::Again));
+                }
+
+                // Requests that have not been admitted yet will not be
+                // dispatched, so reset their streams.
+                self.admitting = FuturesUnordered::new();
                 connection.graceful_shutdown();
 
-                self.state = State::GoAway { connection, error };
+                self.state = State::GoAway {
+                    connection,
+                    error,
+                    abort: false,
+                    give_up: None,
+                };
 
                 Ok(Async::Ready(PollMain::Again))
             }
@@ -362,12 +1054,92 @@ where
         }
     }
 
-    fn poll_goaway(&mut self) -> Poll<(), Error<S>> {
+    fn poll_recreate(&mut self) -> Poll<(), Error<S, A>> {
+        // `None` once the connection has closed.
+        let result = match self.state {
+            State::Recreate {
+                ref mut connection,
+                ref mut future,
+            } => loop {
+                match future.poll() {
+                    Ok(Async::Ready(service)) => break Some(Ok(service)),
+                    Ok(Async::NotReady) => {}
+                    Err(err) => break Some(Err(Error::NewService(err))),
+                }
+
+                let next = connection.poll().map_err(Error::Protocol);
+
+                match try_ready!(next) {
+                    Some((_, mut respond)) => {
+                        if let Some(ref mut idle) = self.idle {
+                            idle.request_received(&self.config.timer);
+                        }
+
+                        debug!("service unavailable; refusing stream");
+                        respond.send_reset(h2::Reason::REFUSED_STREAM);
+                    }
+                    None => break None,
+                }
+            },
+            _ => unreachable!(),
+        };
+
+        self.state = match (mem::replace(&mut self.state, State::Done), result) {
+            (State::Recreate { connection, .. }, Some(Ok(service))) => State::Ready {
+                connection,
+                service,
+            },
+            (State::Recreate { mut connection, .. }, Some(Err(error))) => {
+                connection.graceful_shutdown();
+
+                State::GoAway {
+                    connection,
+                    error,
+                    abort: false,
+                    give_up: None,
+                }
+            }
+            (_, None) => State::Done,
+            _ => unreachable!(),
+        };
+
+        if let State::Ready { .. } = self.state {
+            // Yield before polling the new service, so that services that fail
+            // as soon as they are made do not keep this task from yielding.
+            task::current().notify();
+            return Ok(Async::NotReady);
+        }
+
+        Ok(().into())
+    }
+
+    fn poll_goaway(&mut self) -> Poll<(), Error<S, A>> {
         match self.state {
             State::GoAway {
-                ref mut connection, ..
+                ref mut connection,
+                ref mut abort,
+                ref mut give_up,
+                ..
             } => {
-                try_ready!(connection.poll_close().map_err(Error::Protocol));
+                // Polling the connection sends any resets queued by the
+                // in-flight streams' tasks.
+                let mut poll = connection.poll_close().map_err(Error::Protocol)?;
+
+                if *abort && poll.is_not_ready() && self.signal.in_flight() == 0 {
+                    *abort = false;
+                    connection.abrupt_shutdown(h2::Reason::CANCEL);
+                    poll = connection.poll_close().map_err(Error::Protocol)?;
+                }
+
+                if poll.is_not_ready() {
+                    if !give_up.as_mut().map_or(false, timer::poll_elapsed) {
+                        return Ok(Async::NotReady);
+                    }
+
+                    // The client has stopped reading, so sending it anything
+                    // more may never complete.
+                    debug!("client unresponsive; dropping connection");
+                }
             }
             _ => unreachable!(),
         }
@@ -384,6 +1156,45 @@ where
     }
 }
 
+// ===== impl Config =====
+
+impl<B> Config<B> {
+    fn new() -> Self {
+        Config {
+            timer: Timer::default(),
+            drain_timeout: None,
+            idle_timeout: None,
+            keepalive: None,
+            deadline: None,
+            error_responder: None,
+            reset_reasons: Arc::new(ResetReasons::default()),
+            max_in_flight_per_connection: None,
+            max_in_flight: None,
+            load: Load::new(),
+            max_recreates: DEFAULT_MAX_RECREATES,
+        }
+    }
+}
+
+// B doesn't need to be Clone, since the error responder is shared.
+impl<B> Clone for Config<B> {
+    fn clone(&self) -> Self {
+        Config {
+            timer: self.timer.clone(),
+            drain_timeout: self.drain_timeout,
+            idle_timeout: self.idle_timeout,
+            keepalive: self.keepalive,
+            deadline: self.deadline.clone(),
+            error_responder: self.error_responder.clone(),
+            reset_reasons: self.reset_reasons.clone(),
+            max_in_flight_per_connection: self.max_in_flight_per_connection,
+            max_in_flight: self.max_in_flight,
+            load: self.load.clone(),
+            max_recreates: self.max_recreates,
+        }
+    }
+}
+
 // ===== impl Modify =====
 
 impl<T> Modify for T
@@ -399,6 +1210,69 @@ impl Modify for () {
     fn modify(&mut self, _: &mut Request<()>) {}
 }
 
+// ===== impl ErrorResponder =====
+
+impl<B, F> ErrorResponder<B> for F
+where
+    F: Fn(&(dyn error::Error + 'static)) -> Option<Response<B>>,
+{
+    fn respond(&self, error: &(dyn error::Error + 'static)) -> Option<Response<B>> {
+        (*self)(error)
+    }
+}
+
+// ===== impl Admitting =====
+
+impl<F, B> Future for Admitting<F, B>
+where
+    F: Future<Item = Request<RecvBody>, Error = Reject<B>>,
+    B: Body,
+{
+    type Item = (Accepted<B>, Result<Request<RecvBody>, Reject<B>>);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, ()> {
+        let admitted = {
+            let stream = self.stream.as_mut().expect("polled after complete");
+
+            // Check if the client has reset this stream, which also registers
+            // this task to be notified if it does later.
+            match stream.respond.poll_reset() {
+                Ok(Async::Ready(reason)) => {
+                    debug!("stream received RST_FRAME before admission: {:?}", reason);
+                    stream.reset.cancel(reason);
+                    return Err(());
+                }
+                Ok(Async::NotReady) => {}
+                Err(err) => {
+                    debug!("stream poll_reset received error: {}", err);
+                    return Err(());
+                }
+            }
+
+            match self.future.poll() {
+                Ok(Async::Ready(request)) => Ok(request),
+                Ok(Async::NotReady) => {
+                    if let Some(ref mut deadline) = stream.deadline {
+                        if deadline.poll_expired() {
+                            debug!("response deadline passed before admission; abandoning stream");
+                            stream.reset.cancel(h2::Reason::CANCEL);
+                            deadline.respond(&mut stream.respond);
+                            return Err(());
+                        }
+                    }
+
+                    return Ok(Async::NotReady);
+                }
+                Err(reject) => Err(reject),
+            }
+        };
+
+        let stream = self.stream.take().expect("polled after complete");
+        Ok(Async::Ready((stream, admitted)))
+    }
+}
+
 // ===== impl Background =====
 
 impl<T, B> Background<T, B>
@@ -406,9 +1280,55 @@ where
     T: Future,
     B: Body,
 {
-    fn new(respond: SendResponse<SendBuf<B::Data>>, response: T) -> Self {
+    fn new(accepted: Accepted<B>, response: T, config: Arc<Config<B>>, canceled: Canceled) -> Self {
+        let Accepted {
+            respond,
+            deadline,
+            reset,
+            in_flight,
+            permit,
+        } = accepted;
+
         Background {
             state: BackgroundState::Respond { respond, response },
+            deadline,
+            config,
+            reset,
+            on_flush: None,
+            canceled,
+            _in_flight: in_flight,
+            _permit: permit,
+        }
+    }
+
+    /// Returns a `Background` that sends the response a request was rejected
+    /// with.
+    fn reject(
+        accepted: Accepted<B>,
+        response: Response<B>,
+        config: Arc<Config<B>>,
+        canceled: Canceled,
+    ) -> Self {
+        let Accepted {
+            respond,
+            reset,
+            in_flight,
+            permit,
+            ..
+        } = accepted;
+
+        Background {
+            state: BackgroundState::Reject {
+                respond,
+                response: Some(response),
+            },
+            deadline: None,
+            config,
+            reset,
+            on_flush: None,
+            canceled,
+            _in_flight: in_flight,
+            _permit: permit,
         }
     }
 }
@@ -426,18 +1346,36 @@ where
     fn poll(&mut self) -> Poll<(), ()> {
         use self::BackgroundState::*;
 
+        // The connection is being forcibly closed, so give up on the stream.
+        if self.canceled.poll_canceled() {
+            debug!("connection closing; resetting stream");
+            self.reset.cancel(h2::Reason::CANCEL);
+            match self.state {
+                Respond {
+                    ref mut respond, ..
+                }
+                | Reject {
+                    ref mut respond, ..
+                } => respond.send_reset(h2::Reason::CANCEL),
+                Flush(ref mut flush) => flush.send_reset(h2::Reason::CANCEL),
+            }
+            if let Some(on_flush) = self.on_flush.take() {
+                on_flush.complete(FlushOutcome::Reset(h2::Reason::CANCEL));
+            }
+            return Ok(().into());
+        }
+
         loop {
-            let flush = match self.state {
+            let (respond, response) = match self.state {
                 Respond {
                     ref mut respond,
                     ref mut response,
                 } => {
-                    use flush::Flush;
-
                     // Check if the client has reset this stream...
                     match respond.poll_reset() {
                         Ok(Async::Ready(reason)) => {
                             debug!("stream received RST_FRAME: {:?}", reason);
+                            self.reset.cancel(reason);
                             return Ok(().into());
                         }
                         Ok(Async::NotReady) => {
@@ -452,37 +1390,106 @@ where
                         }
                     }
 
-                    let response = try_ready!(response.poll().map_err(|err| {
-                        let err = err.into();
-                        debug!("user service error: {}", err);
-                        let reason = ::error::reason_from_dyn_error(&*err);
-                        respond.send_reset(reason);
-                    }));
-
-                    let (parts, body) = response.into_parts();
-
-                    // Check if the response is immediately an end-of-stream.
-                    let eos = body.is_end_stream();
-
-                    // Try sending the response.
-                    let response = Response::from_parts(parts, ());
-                    match respond.send_response(response, eos) {
-                        Ok(stream) => {
-                            if eos {
-                                // Nothing more to do
-                                return Ok(().into());
+                    let response = match response.poll() {
+                        Ok(Async::Ready(response)) => response,
+                        Err(err) => {
+                            let err = err.into();
+                            debug!("user service error: {}", err);
+
+                            let response = self
+                                .config
+                                .error_responder
+                                .as_ref()
+                                .and_then(|responder| responder.respond(&*err));
+
+                            match response {
+                                Some(response) => response,
+                                None => {
+                                    let reason = self.config.reset_reasons.reason(&*err);
+                                    respond.send_reset(reason);
+                                    return Err(());
+                                }
+                            }
+                        }
+                        Ok(Async::NotReady) => {
+                            if let Some(ref mut deadline) = self.deadline {
+                                if deadline.poll_expired() {
+                                    debug!("response deadline passed; abandoning stream");
+                                    self.reset.cancel(h2::Reason::CANCEL);
+                                    deadline.respond(respond);
+                                    return Ok(().into());
+                                }
                             }
 
-                            // Transition to flushing the body
-                            Flush::new(body, stream)
+                            return Ok(Async::NotReady);
+                        }
+                    };
+
+                    (respond, response)
+                }
+                Reject {
+                    ref mut respond,
+                    ref mut response,
+                } => {
+                    let response = response.take().expect("polled after complete");
+                    (respond, response)
+                }
+                Flush(ref mut flush) => {
+                    return match flush.poll_complete() {
+                        Ok(Async::Ready(())) => {
+                            if let Some(on_flush) = self.on_flush.take() {
+                                on_flush.complete(FlushOutcome::Complete);
+                            }
+                            Ok(Async::Ready(()))
                         }
+                        Ok(Async::NotReady) => Ok(Async::NotReady),
                         Err(err) => {
-                            warn!("error sending response: {:?}", err);
-                            return Ok(().into());
+                            warn!("error flushing stream: {:?}", err);
+                            if let Some(reason) = err.reason() {
+                                self.reset.cancel(reason);
+                            }
+                            if let Some(on_flush) = self.on_flush.take() {
+                                on_flush.failed(err.reason());
+                            }
+                            Err(())
+                        }
+                    };
+                }
+            };
+
+            let (mut parts, body) = response.into_parts();
+            self.on_flush = parts.extensions.remove::<OnFlush>();
+
+            // Check if the response is immediately an end-of-stream.
+            let eos = body.is_end_stream();
+
+            // Try sending the response.
+            let response = Response::from_parts(parts, ());
+            let flush = match respond.send_response(response, eos) {
+                Ok(stream) => {
+                    if eos {
+                        // Nothing more to do
+                        if let Some(on_flush) = self.on_flush.take() {
+                            on_flush.complete(FlushOutcome::Complete);
                         }
+                        return Ok(().into());
+                    }
+
+                    // Transition to flushing the body
+                    let reasons = self.config.reset_reasons.clone();
+                    let mut flush = flush::Flush::new(body, stream, reasons);
+                    if let Some(ref on_flush) = self.on_flush {
+                        flush.set_body_error(on_flush.body_error());
+                    }
+                    flush
+                }
+                Err(err) => {
+                    warn!("error sending response: {:?}", err);
+                    if let Some(on_flush) = self.on_flush.take() {
+                        on_flush.failed(err.reason());
                     }
+                    return Ok(().into());
                 }
-                Flush(ref mut flush) => return flush.poll(),
             };
 
             self.state = Flush(flush);
@@ -490,11 +1497,23 @@ where
     }
 }
 
+impl<T, B> Drop for Background<T, B>
+where
+    B: Body,
+{
+    fn drop(&mut self) {
+        // The task was dropped before the response was sent.
+        if let Some(on_flush) = self.on_flush.take() {
+            on_flush.complete(FlushOutcome::Closed);
+        }
+    }
+}
+
 // ===== impl Error =====
 
-impl<S> Error<S>
+impl<S, A> Error<S, A>
 where
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
 {
     fn from_init(err: Either<h2::Error, S::MakeError>) -> Self {
         match err {
@@ -504,9 +1523,9 @@ where
     }
 }
 
-impl<S> fmt::Debug for Error<S>
+impl<S, A> fmt::Debug for Error<S, A>
 where
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
     S::MakeError: fmt::Debug,
     S::Error: fmt::Debug,
 {
@@ -517,13 +1536,15 @@ where
             Error::NewService(ref why) => f.debug_tuple("NewService").field(why).finish(),
             Error::Service(ref why) => f.debug_tuple("Service").field(why).finish(),
             Error::Execute => f.debug_tuple("Execute").finish(),
+            Error::DrainTimeout => f.debug_tuple("DrainTimeout").finish(),
+            Error::KeepAliveTimeout => f.debug_tuple("KeepAliveTimeout").finish(),
         }
     }
 }
 
-impl<S> fmt::Display for Error<S>
+impl<S, A> fmt::Display for Error<S, A>
 where
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
     S::MakeError: fmt::Display,
     S::Error: fmt::Display,
 {
@@ -538,13 +1559,17 @@ where
             }
             Error::Service(ref why) => write!(f, "Error returned by service: {}", why),
             Error::Execute => write!(f, "Error occurred while attempting to spawn a task"),
+            Error::DrainTimeout => write!(f, "Connection forcibly closed after drain timeout"),
+            Error::KeepAliveTimeout => {
+                write!(f, "Connection forcibly closed after keepalive PING timed out")
+            }
         }
     }
 }
 
-impl<S> error::Error for Error<S>
+impl<S, A> error::Error for Error<S, A>
 where
-    S: MakeService<(), Request<RecvBody>>,
+    S: MakeService<A, Request<RecvBody>>,
     S::MakeError: error::Error,
     S::Error: error::Error,
 {
@@ -555,6 +1580,8 @@ where
             Error::NewService(ref why) => Some(why),
             Error::Service(ref why) => Some(why),
             Error::Execute => None,
+            Error::DrainTimeout => None,
+            Error::KeepAliveTimeout => None,
         }
     }
 
@@ -565,6 +1592,15 @@ where
             Error::NewService(_) => "error occured while obtaining service",
             Error::Service(_) => "error returned by service",
             Error::Execute => "error occurred while attempting to spawn a task",
+            Error::DrainTimeout => "connection forcibly closed after drain timeout",
+            Error::KeepAliveTimeout => "connection forcibly closed after keepalive PING timed out",
         }
     }
 }
+
+fn insert_target<A>(target: &Arc<A>, extensions: &mut Extensions)
+where
+    A: Send + Sync + 'static,
+{
+    extensions.insert(target.clone());
+}
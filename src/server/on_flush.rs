@@ -0,0 +1,87 @@
+use error::BodyError;
+
+use h2;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Notified once a response has been sent, or has failed to be sent.
+///
+/// A service inserts an `OnFlush` into the extensions of a response to learn
+/// whether its body was delivered, such as to account for a streaming
+/// response or to clean up after it. The callback is invoked exactly once,
+/// from the task that sends the response.
+pub struct OnFlush {
+    callback: Mutex<Option<Callback>>,
+    body_error: Arc<Mutex<Option<BodyError>>>,
+}
+
+type Callback = Box<dyn FnOnce(FlushOutcome) + Send>;
+
+/// How sending a response ended.
+#[derive(Debug)]
+pub enum FlushOutcome {
+    /// The response, including its entire body, was sent.
+    Complete,
+
+    /// The stream was reset before the body was sent, either by the client,
+    /// in which case this is the client's reason, or by the server when it
+    /// forcibly closed the connection.
+    Reset(h2::Reason),
+
+    /// The response body failed, so the stream was reset.
+    Body(BodyError),
+
+    /// The connection failed or was dropped before the body was sent.
+    Closed,
+}
+
+// ===== impl OnFlush =====
+
+impl OnFlush {
+    /// Returns an `OnFlush` that calls `callback` with the outcome of sending
+    /// the response.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: FnOnce(FlushOutcome) + Send + 'static,
+    {
+        OnFlush {
+            callback: Mutex::new(Some(Box::new(callback))),
+            body_error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the slot that the response body's error is stored in.
+    pub(crate) fn body_error(&self) -> Arc<Mutex<Option<BodyError>>> {
+        self.body_error.clone()
+    }
+
+    /// Report that sending the response failed because its stream was reset
+    /// with `reason`, or, if there is no reason, because the connection
+    /// closed.
+    pub(crate) fn failed(self, reason: Option<h2::Reason>) {
+        let body_error = self.body_error.lock().unwrap().take();
+
+        let outcome = match (body_error, reason) {
+            (Some(body_error), _) => FlushOutcome::Body(body_error),
+            (None, Some(reason)) => FlushOutcome::Reset(reason),
+            (None, None) => FlushOutcome::Closed,
+        };
+
+        self.complete(outcome);
+    }
+
+    /// Report the `outcome` of sending the response.
+    pub(crate) fn complete(self, outcome: FlushOutcome) {
+        let callback = self.callback.lock().unwrap().take();
+        if let Some(callback) = callback {
+            callback(outcome);
+        }
+    }
+}
+
+impl fmt::Debug for OnFlush {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OnFlush").finish()
+    }
+}
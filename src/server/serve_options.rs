@@ -0,0 +1,67 @@
+use super::ServerSettings;
+
+/// Describes how `Server::serve_with` serves a single connection.
+///
+/// By default, the connection's target is `()`, every request is admitted
+/// unmodified, and the server's own SETTINGS are advertised.
+#[derive(Clone, Debug)]
+pub struct ServeOptions<A = (), F = ()> {
+    pub(crate) target: A,
+    pub(crate) admit: F,
+    pub(crate) settings: ServerSettings,
+}
+
+// ===== impl ServeOptions =====
+
+impl ServeOptions {
+    /// Returns the default `ServeOptions`.
+    pub fn new() -> Self {
+        ServeOptions {
+            target: (),
+            admit: (),
+            settings: ServerSettings::default(),
+        }
+    }
+}
+
+impl<A, F> ServeOptions<A, F> {
+    /// Obtains the connection's service by passing `target` to the
+    /// `MakeService`.
+    ///
+    /// `target` describes the connection, such as with a `ConnectionInfo`.
+    pub fn target<T>(self, target: T) -> ServeOptions<T, F> {
+        ServeOptions {
+            target,
+            admit: self.admit,
+            settings: self.settings,
+        }
+    }
+
+    /// Passes each request through `admit` before the service.
+    ///
+    /// `admit` may be a `Modify`, or an `Admit` to reject requests or to
+    /// decide on them asynchronously.
+    pub fn admit<G>(self, admit: G) -> ServeOptions<A, G> {
+        ServeOptions {
+            target: self.target,
+            admit,
+            settings: self.settings,
+        }
+    }
+
+    /// Advertises `settings` in place of the server's initial SETTINGS.
+    ///
+    /// Settings that are not specified in `settings` fall back to those set
+    /// with `Server::set_settings`, and then to the `h2::server::Builder` the
+    /// server was created with.
+    pub fn settings(mut self, settings: ServerSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions::new()
+    }
+}
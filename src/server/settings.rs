@@ -0,0 +1,86 @@
+use h2;
+
+/// Initial HTTP/2.0 SETTINGS advertised by a server connection.
+///
+/// Every setting is optional. Settings that are left unset fall back to the
+/// value configured on the `h2::server::Builder` that the `Server` was
+/// created with, so a `ServerSettings` only has to describe the values that
+/// differ for a given listener.
+///
+/// Servers never initiate server push, so `SETTINGS_ENABLE_PUSH` is not
+/// configurable here: the server always behaves as though push is disabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerSettings {
+    initial_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    max_concurrent_streams: Option<u32>,
+    max_frame_size: Option<u32>,
+    max_header_list_size: Option<u32>,
+}
+
+// ===== impl ServerSettings =====
+
+impl ServerSettings {
+    /// Returns a new `ServerSettings` with no settings specified.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `SETTINGS_INITIAL_WINDOW_SIZE`, the stream-level flow control
+    /// window for received data.
+    pub fn initial_window_size(&mut self, size: u32) -> &mut Self {
+        self.initial_window_size = Some(size);
+        self
+    }
+
+    /// Sets the connection-level flow control window for received data.
+    pub fn initial_connection_window_size(&mut self, size: u32) -> &mut Self {
+        self.initial_connection_window_size = Some(size);
+        self
+    }
+
+    /// Sets `SETTINGS_MAX_CONCURRENT_STREAMS`, the maximum number of streams
+    /// the client may have open at once.
+    pub fn max_concurrent_streams(&mut self, max: u32) -> &mut Self {
+        self.max_concurrent_streams = Some(max);
+        self
+    }
+
+    /// Sets `SETTINGS_MAX_FRAME_SIZE`, the largest frame payload the server is
+    /// willing to receive.
+    pub fn max_frame_size(&mut self, max: u32) -> &mut Self {
+        self.max_frame_size = Some(max);
+        self
+    }
+
+    /// Sets `SETTINGS_MAX_HEADER_LIST_SIZE`, the largest header list the
+    /// server is willing to accept.
+    pub fn max_header_list_size(&mut self, max: u32) -> &mut Self {
+        self.max_header_list_size = Some(max);
+        self
+    }
+
+    /// Apply the specified settings to `builder`, leaving all other settings
+    /// untouched.
+    pub(crate) fn apply(&self, builder: &mut h2::server::Builder) {
+        if let Some(size) = self.initial_window_size {
+            builder.initial_window_size(size);
+        }
+
+        if let Some(size) = self.initial_connection_window_size {
+            builder.initial_connection_window_size(size);
+        }
+
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+
+        if let Some(max) = self.max_frame_size {
+            builder.max_frame_size(max);
+        }
+
+        if let Some(max) = self.max_header_list_size {
+            builder.max_header_list_size(max);
+        }
+    }
+}
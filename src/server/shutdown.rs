@@ -0,0 +1,349 @@
+use futures::future::Shared;
+use futures::sync::oneshot;
+use futures::task::{self, AtomicTask, Task};
+use futures::{Async, Future, Poll};
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::{fmt, mem};
+
+/// Triggers a graceful shutdown of a single `Connection`.
+///
+/// Unlike `Connection::graceful_shutdown`, a `ShutdownHandle` may be used
+/// after the `Connection` has been moved onto an executor.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    signal: Arc<Signal>,
+}
+
+/// Triggers a graceful shutdown of every connection served by a `Server`.
+///
+/// Connections served after the drain has started are shut down as soon as
+/// they are polled.
+#[derive(Clone)]
+pub struct DrainHandle {
+    registry: Arc<Registry>,
+}
+
+/// Completes once the connections being shut down have closed and all of
+/// their in-flight response tasks have finished.
+pub struct Drained {
+    signals: Vec<Arc<Signal>>,
+}
+
+/// Shutdown state shared by a `Connection`, its `Background` tasks, and any
+/// `ShutdownHandle`s.
+pub(crate) struct Signal {
+    /// Set once a graceful shutdown has been requested.
+    shutdown: AtomicBool,
+
+    /// The connection task, notified when a shutdown is requested.
+    task: AtomicTask,
+
+    /// The number of live `Active` guards.
+    active: AtomicUsize,
+
+    /// Tasks waiting for `active` to reach zero.
+    idle: Mutex<Vec<Task>>,
+
+    /// The number of live `InFlight` guards.
+    in_flight: AtomicUsize,
+
+    /// Set when `in_flight` drops to zero, until the connection observes it.
+    emptied: AtomicBool,
+}
+
+/// Prevents a `Signal` from being considered drained while it is held.
+///
+/// One is held by the `Connection` until it completes, and one by each
+/// `Background` task it spawns.
+pub(crate) struct Active {
+    signal: Arc<Signal>,
+}
+
+/// Held by each `Background` task while its stream is in flight.
+pub(crate) struct InFlight {
+    signal: Arc<Signal>,
+    _active: Active,
+}
+
+/// Held by a `Connection` to reset the streams of its `Background` tasks when
+/// it is forcibly closed.
+pub(crate) struct Cancel(oneshot::Sender<()>);
+
+/// Held by each `Background` task to learn that its stream must be reset.
+#[derive(Clone)]
+pub(crate) struct Canceled(Option<Shared<oneshot::Receiver<()>>>);
+
+/// Tracks the connections served by a `Server`.
+pub(crate) struct Registry {
+    draining: AtomicBool,
+    signals: Mutex<Vec<Weak<Signal>>>,
+}
+
+// ===== impl ShutdownHandle =====
+
+impl ShutdownHandle {
+    pub(crate) fn new(signal: Arc<Signal>) -> Self {
+        ShutdownHandle { signal }
+    }
+
+    /// Start an HTTP/2.0 graceful shutdown of the connection.
+    ///
+    /// The returned future completes once the connection has closed and all
+    /// of its in-flight response tasks have finished. It does not need to be
+    /// polled for the shutdown to proceed.
+    pub fn shutdown(&self) -> Drained {
+        self.signal.shutdown();
+
+        Drained {
+            signals: vec![self.signal.clone()],
+        }
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShutdownHandle")
+            .field("shutdown", &self.signal.is_shutdown())
+            .finish()
+    }
+}
+
+// ===== impl DrainHandle =====
+
+impl DrainHandle {
+    pub(crate) fn new(registry: Arc<Registry>) -> Self {
+        DrainHandle { registry }
+    }
+
+    /// Start an HTTP/2.0 graceful shutdown of every connection.
+    ///
+    /// The returned future completes once all of the connections have closed
+    /// and all of their in-flight response tasks have finished.
+    pub fn drain(&self) -> Drained {
+        self.registry.drain()
+    }
+}
+
+impl fmt::Debug for DrainHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrainHandle")
+            .field("draining", &self.registry.draining.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+// ===== impl Drained =====
+
+impl Future for Drained {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.signals.retain(|signal| signal.poll_idle().is_not_ready());
+
+        if self.signals.is_empty() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl fmt::Debug for Drained {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Drained")
+            .field("remaining", &self.signals.len())
+            .finish()
+    }
+}
+
+// ===== impl Signal =====
+
+impl Signal {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Signal {
+            shutdown: AtomicBool::new(false),
+            task: AtomicTask::new(),
+            active: AtomicUsize::new(0),
+            idle: Mutex::new(Vec::new()),
+            in_flight: AtomicUsize::new(0),
+            emptied: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the number of streams that are currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the last in-flight stream has completed since this
+    /// was last called.
+    pub fn take_emptied(&self) -> bool {
+        self.emptied.swap(false, Ordering::SeqCst)
+    }
+
+    /// Request a graceful shutdown, notifying the connection task.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.task.notify();
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if a graceful shutdown has been requested.
+    ///
+    /// Otherwise, the current task is notified when one is requested.
+    pub fn poll_shutdown(&self) -> bool {
+        self.task.register();
+        self.is_shutdown()
+    }
+
+    /// Returns `Ready` once no `Active` guards remain.
+    fn poll_idle(&self) -> Async<()> {
+        if self.active.load(Ordering::SeqCst) == 0 {
+            return Async::Ready(());
+        }
+
+        {
+            let mut idle = self.idle.lock().unwrap();
+            if !idle.iter().any(Task::will_notify_current) {
+                idle.push(task::current());
+            }
+        }
+
+        // The last guard may have been dropped while registering.
+        if self.active.load(Ordering::SeqCst) == 0 {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        }
+    }
+}
+
+// ===== impl Active =====
+
+impl Active {
+    pub fn new(signal: Arc<Signal>) -> Self {
+        signal.active.fetch_add(1, Ordering::SeqCst);
+        Active { signal }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        if self.signal.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let idle = mem::replace(&mut *self.signal.idle.lock().unwrap(), Vec::new());
+            for task in idle {
+                task.notify();
+            }
+        }
+    }
+}
+
+// ===== impl InFlight =====
+
+impl InFlight {
+    pub fn new(signal: Arc<Signal>) -> Self {
+        signal.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        InFlight {
+            _active: Active::new(signal.clone()),
+            signal,
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.signal.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.signal.emptied.store(true, Ordering::SeqCst);
+            self.signal.task.notify();
+        }
+    }
+}
+
+// ===== impl Cancel =====
+
+pub(crate) fn cancel() -> (Cancel, Canceled) {
+    let (tx, rx) = oneshot::channel();
+    (Cancel(tx), Canceled(Some(rx.shared())))
+}
+
+impl Cancel {
+    /// Notify all `Canceled` handles that their streams must be reset.
+    pub fn cancel(self) {
+        let _ = self.0.send(());
+    }
+}
+
+// ===== impl Canceled =====
+
+impl Canceled {
+    /// Returns `true` if the connection has canceled its streams.
+    ///
+    /// Otherwise, the current task is notified if it does.
+    pub fn poll_canceled(&mut self) -> bool {
+        let canceled = match self.0 {
+            Some(ref mut rx) => match rx.poll() {
+                Ok(Async::Ready(_)) => true,
+                Ok(Async::NotReady) => return false,
+                // The connection went away without canceling anything.
+                Err(_) => false,
+            },
+            None => return false,
+        };
+
+        self.0 = None;
+        canceled
+    }
+}
+
+// ===== impl Registry =====
+
+impl Registry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Registry {
+            draining: AtomicBool::new(false),
+            signals: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Returns the `Signal` for a newly served connection.
+    pub fn register(&self) -> Arc<Signal> {
+        let signal = Signal::new();
+
+        let mut signals = self.signals.lock().unwrap();
+
+        // Forget about connections that have since gone away.
+        signals.retain(|signal| signal.upgrade().is_some());
+        signals.push(Arc::downgrade(&signal));
+
+        if self.draining.load(Ordering::SeqCst) {
+            signal.shutdown();
+        }
+
+        signal
+    }
+
+    fn drain(&self) -> Drained {
+        self.draining.store(true, Ordering::SeqCst);
+
+        let signals = self
+            .signals
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+
+        for signal in &signals {
+            signal.shutdown();
+        }
+
+        Drained { signals }
+    }
+}
@@ -0,0 +1,82 @@
+use futures::{Async, Future};
+use tokio_timer::clock::{self, Clock};
+use tokio_timer::timer::Handle;
+use tokio_timer::Delay;
+
+use std::time::{Duration, Instant};
+
+/// The source of time used to drive timeouts.
+///
+/// By default, a `Timer` uses the timer and clock of the execution context in
+/// which a timeout is started, which is usually the tokio runtime's. A `Timer`
+/// may instead be given an explicit timer `Handle` and `Clock`. For example,
+/// tests may use a `Clock` backed by a mock `Now` to control the passage of
+/// time.
+#[derive(Clone, Debug, Default)]
+pub struct Timer {
+    handle: Option<Handle>,
+    clock: Option<Clock>,
+}
+
+// ===== impl Timer =====
+
+impl Timer {
+    /// Returns a `Timer` that uses the current execution context's timer and
+    /// clock.
+    pub fn new() -> Self {
+        Timer::default()
+    }
+
+    /// Returns a `Timer` that uses the provided timer `handle` and `clock`.
+    ///
+    /// The clock should be the same one that drives `handle`.
+    pub fn with_handle(handle: Handle, clock: Clock) -> Self {
+        Timer {
+            handle: Some(handle),
+            clock: Some(clock),
+        }
+    }
+
+    /// Returns the current instant, according to this timer's clock.
+    pub(crate) fn now(&self) -> Instant {
+        match self.clock {
+            Some(ref clock) => clock.now(),
+            None => clock::now(),
+        }
+    }
+
+    /// Returns a `Delay` that elapses `duration` from now.
+    pub(crate) fn delay(&self, duration: Duration) -> Delay {
+        self.delay_until(self.now() + duration)
+    }
+
+    /// Returns a `Delay` that elapses `duration` from now, or `None` if that
+    /// instant is too far in the future to be represented.
+    pub(crate) fn checked_delay(&self, duration: Duration) -> Option<Delay> {
+        self.now()
+            .checked_add(duration)
+            .map(|deadline| self.delay_until(deadline))
+    }
+
+    fn delay_until(&self, deadline: Instant) -> Delay {
+        match self.handle {
+            Some(ref handle) => handle.delay(deadline),
+            None => Delay::new(deadline),
+        }
+    }
+}
+
+/// Returns `true` once `delay` has elapsed.
+///
+/// Otherwise, the current task is notified when it elapses. A timer that has
+/// failed is logged and treated as though it will never elapse.
+pub(crate) fn poll_elapsed(delay: &mut Delay) -> bool {
+    match delay.poll() {
+        Ok(Async::Ready(())) => true,
+        Ok(Async::NotReady) => false,
+        Err(e) => {
+            warn!("timer failed: {}", e);
+            false
+        }
+    }
+}
@@ -16,18 +16,25 @@ use std::cell::RefCell;
 mod support;
 
 struct MockConn {
-    conn: RefCell<Option<Mock>>,
+    conns: RefCell<Vec<Option<Mock>>>,
 }
 
 impl MockConn {
     fn new(mock: Mock) -> Self {
+        MockConn::many(vec![Some(mock)])
+    }
+
+    /// Yields each of `mocks` in turn, one per connection. A `None` refuses
+    /// that connection.
+    fn many(mut mocks: Vec<Option<Mock>>) -> Self {
+        mocks.reverse();
         MockConn {
-            conn: RefCell::new(Some(mock))
+            conns: RefCell::new(mocks)
         }
     }
 }
 
-impl Service<()> for MockConn {
+impl<T> Service<T> for MockConn {
     type Response = Mock;
     type Error = ::std::io::Error;
     type Future = FutureResult<Mock, ::std::io::Error>;
@@ -36,8 +43,11 @@ impl Service<()> for MockConn {
         Ok(().into())
     }
 
-    fn call(&mut self, _: ()) -> Self::Future {
-        future::ok(self.conn.borrow_mut().take().expect("connected more than once!"))
+    fn call(&mut self, _: T) -> Self::Future {
+        match self.conns.borrow_mut().pop().expect("connected too many times!") {
+            Some(mock) => future::ok(mock),
+            None => future::err(::std::io::ErrorKind::ConnectionRefused.into()),
+        }
     }
 }
 
@@ -213,3 +223,1165 @@ fn hello_bodies() {
         .block_on(done.join(srv))
         .unwrap();
 }
+
+#[test]
+fn keepalive_timeout_fails_connection() {
+    use futures::sync::oneshot;
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+    use tokio_timer::clock::Clock;
+    use tower_h2::KeepAlive;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+    let now = MockNow::new();
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                now.advance(Duration::from_secs(11));
+                Ok(v)
+            }
+        })
+        .recv_frame(frames::ping(frame::Ping::USER))
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                // Never acknowledge the PING.
+                now.advance(Duration::from_secs(6));
+                Ok(v)
+            }
+        })
+        // Hold the connection open until the client has given up on it.
+        .and_then(|v| done_rx.map(move |_| v).map_err(|_| panic!("client dropped")))
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    h2.set_keepalive(Some(KeepAlive::new(
+        Duration::from_secs(10),
+        Duration::from_secs(5),
+    )));
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            h2.call(http::Request::builder()
+                .method("GET")
+                .uri("https://example.com/")
+                .body(NoBody)
+                .unwrap())
+                .then(move |res| {
+                    match res {
+                        Err(ref e) if e.is_keepalive_timeout() => {}
+                        Err(e) => panic!("unexpected error: {:?}", e),
+                        Ok(_) => panic!("unexpected response"),
+                    }
+
+                    match h2.poll_ready() {
+                        Err(ref e) if e.is_keepalive_timeout() => {}
+                        res => panic!("unexpected poll_ready: {:?}", res),
+                    }
+
+                    let _ = done_tx.send(());
+                    Ok(())
+                })
+        });
+
+    let mut rt = current_thread::Builder::new()
+        .clock(Clock::new_with_now(now))
+        .build()
+        .unwrap();
+    rt.block_on(done.join(srv)).unwrap();
+}
+
+#[test]
+fn connection_state_reflects_go_away() {
+    use tower_h2::client::ConnectionState;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .send_frame(frames::go_away(1))
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            assert_eq!(h2.state(), ConnectionState::Open);
+            assert!(h2.go_away().is_none());
+
+            h2.call(http::Request::builder()
+                .method("GET")
+                .uri("https://example.com/")
+                .body(NoBody)
+                .unwrap())
+                .map(move |rsp| {
+                    assert_eq!(rsp.status(), http::StatusCode::OK);
+                    assert_eq!(h2.state(), ConnectionState::Draining);
+
+                    let go_away = h2.go_away().expect("go_away");
+                    assert_eq!(go_away.last_stream_id(), 1.into());
+                    assert_eq!(go_away.reason(), tower_h2::Reason::NO_ERROR);
+                })
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+}
+
+#[test]
+fn connection_state_reflects_go_away_split_across_reads() {
+    use tower_h2::client::ConnectionState;
+
+    let _ = ::env_logger::try_init();
+
+    // A connection-level WINDOW_UPDATE, followed by a GOAWAY with a last
+    // stream ID of 1 and ENHANCE_YOUR_CALM.
+    let window_update = [0, 0, 4, 0x8, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    let go_away = [0, 0, 8, 0x7, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0xb];
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        // The GOAWAY's header is split, and so is its payload.
+        .send_bytes(&[&window_update[..], &go_away[..5]].concat())
+        .idle_ms(10)
+        .send_bytes(&go_away[5..13])
+        .idle_ms(10)
+        .send_bytes(&go_away[13..])
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            h2.call(http::Request::builder()
+                .method("GET")
+                .uri("https://example.com/")
+                .body(NoBody)
+                .unwrap())
+                .map(move |rsp| {
+                    assert_eq!(rsp.status(), http::StatusCode::OK);
+                    assert_eq!(h2.state(), ConnectionState::Draining);
+
+                    let go_away = h2.go_away().expect("go_away");
+                    assert_eq!(go_away.last_stream_id(), 1.into());
+                    assert_eq!(go_away.reason(), tower_h2::Reason::ENHANCE_YOUR_CALM);
+                })
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+}
+
+#[test]
+fn retry_refused_stream_on_new_connection() {
+    use tower_h2::client::{Retries, Retry};
+
+    let _ = ::env_logger::try_init();
+
+    let (io1, srv1) = mock::new();
+    let (io2, srv2) = mock::new();
+
+    let srv1 = srv1
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+        )
+        .recv_frame(frames::data(1, "hello world").eos())
+        .send_frame(frames::reset(1).refused())
+        .close();
+
+    // The request, including its body, is replayed on a new connection.
+    let srv2 = srv2
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+        )
+        .recv_frame(frames::data(1, "hello world").eos())
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let conn = MockConn::many(vec![Some(io1), Some(io2)]);
+    let h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    let mut retry = Some(Retry::new(h2, ()));
+
+    let done = future::poll_fn(move || {
+        try_ready!(retry.as_mut().unwrap().poll_ready());
+        Ok(retry.take().unwrap().into())
+    })
+        .and_then(|mut retry| {
+            retry.call(http::Request::builder()
+                .method("POST")
+                .uri("https://example.com/")
+                .body(SendBody::new("hello world"))
+                .unwrap())
+        })
+        .map(|rsp| {
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+
+            let retries = rsp.extensions().get::<Retries>().expect("retries");
+            assert_eq!(retries.count(), 1);
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv1.join(srv2)))
+        .unwrap();
+}
+
+#[test]
+fn retries_share_one_new_connection() {
+    use tower_h2::client::{Retries, Retry};
+
+    let _ = ::env_logger::try_init();
+
+    let (io1, srv1) = mock::new();
+    let (io2, srv2) = mock::new();
+
+    // Neither request is processed before the GOAWAY.
+    let srv1 = srv1
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .recv_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .send_frame(frames::go_away(0))
+        .close();
+
+    // Both are retried on the same new connection.
+    let srv2 = srv2
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .recv_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .send_frame(frames::headers(1).response(200).eos())
+        .send_frame(frames::headers(3).response(200).eos())
+        .close();
+
+    let get = || {
+        http::Request::builder()
+            .method("GET")
+            .uri("https://example.com/")
+            .body(NoBody)
+            .unwrap()
+    };
+
+    // A third connection would panic.
+    let conn = MockConn::many(vec![Some(io1), Some(io2)]);
+    let h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    let mut retry = Some(Retry::new(h2, ()));
+
+    let done = future::poll_fn(move || {
+        try_ready!(retry.as_mut().unwrap().poll_ready());
+        Ok(retry.take().unwrap().into())
+    })
+        .and_then(move |mut retry| {
+            let first = retry.call(get());
+            let mut retry = Some(retry);
+
+            future::poll_fn(move || {
+                try_ready!(retry.as_mut().unwrap().poll_ready());
+                Ok(retry.take().unwrap().into())
+            })
+                .and_then(move |mut retry| first.join(retry.call(get())))
+        })
+        .map(|(rsp1, rsp2)| {
+            for rsp in &[rsp1, rsp2] {
+                assert_eq!(rsp.status(), http::StatusCode::OK);
+
+                let retries = rsp.extensions().get::<Retries>().expect("retries");
+                assert_eq!(retries.count(), 1);
+            }
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv1.join(srv2)))
+        .unwrap();
+}
+
+#[test]
+fn retry_ignores_refused_stream_sent_by_client() {
+    use tower_h2::client::{ErrorKind, Retry, RetryError};
+    use tower_h2::ResetReasons;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    // The client resets the stream itself when the body fails.
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(frames::headers(1).request("POST", "https://example.com/"))
+        .recv_frame(frames::reset(1).refused())
+        .close();
+
+    let mut reasons = ResetReasons::new();
+    reasons.io_error(
+        std::io::ErrorKind::BrokenPipe,
+        tower_h2::Reason::REFUSED_STREAM,
+    );
+
+    // A second connection would panic.
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    h2.set_reset_reasons(reasons);
+    let mut retry = Some(Retry::new(h2, ()));
+
+    let done = future::poll_fn(move || {
+        try_ready!(retry.as_mut().unwrap().poll_ready());
+        Ok(retry.take().unwrap().into())
+    })
+    .and_then(|mut retry| {
+        retry.call(
+            http::Request::builder()
+                .method("POST")
+                .uri("https://example.com/")
+                .body(IoFailingBody)
+                .unwrap(),
+        )
+    })
+    .then(|res| {
+        match res.expect_err("request should fail") {
+            RetryError::Request(err) => {
+                assert_eq!(err.kind(), ErrorKind::Body);
+                assert_eq!(err.reason(), Some(tower_h2::Reason::REFUSED_STREAM));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        Ok(())
+    });
+
+    Runtime::new().unwrap().block_on(done.join(srv)).unwrap();
+}
+
+#[test]
+fn reconnect_after_failed_connect() {
+    use std::time::Duration;
+    use tower_h2::client::{Backoff, Reconnect};
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    // The first connection attempt is refused.
+    let conn = MockConn::many(vec![None, Some(io)]);
+    let h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    let mut reconnect = Reconnect::new(h2, ());
+    reconnect.set_backoff(Backoff::new(
+        Duration::from_millis(1),
+        Duration::from_millis(10),
+        0.5,
+    ));
+    let mut reconnect = Some(reconnect);
+
+    let done = future::poll_fn(move || {
+        try_ready!(reconnect.as_mut().unwrap().poll_ready());
+        Ok(reconnect.take().unwrap().into())
+    })
+        .and_then(|mut reconnect| {
+            reconnect.call(http::Request::builder()
+                .method("GET")
+                .uri("https://example.com/")
+                .body(NoBody)
+                .unwrap())
+        })
+        .map(|rsp| {
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+}
+
+#[test]
+fn pool_opens_connection_when_saturated() {
+    use futures::sync::oneshot;
+    use tower_h2::client::Pool;
+
+    let _ = ::env_logger::try_init();
+
+    let (io1, srv1) = mock::new();
+    let (io2, srv2) = mock::new();
+    let (sent_tx, sent_rx) = oneshot::channel::<()>();
+    let (second_tx, second_rx) = oneshot::channel::<()>();
+
+    // The first connection only allows one stream at a time, and holds the
+    // first response until the second request arrives elsewhere.
+    let srv1 = srv1
+        .assert_client_handshake_with_settings(frames::settings().max_concurrent_streams(1))
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .and_then(move |srv| {
+            sent_tx.send(()).unwrap();
+            second_rx.map(move |_| srv).map_err(|e| panic!("{:?}", e))
+        })
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let srv2 = srv2
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .and_then(move |srv| {
+            second_tx.send(()).unwrap();
+            Ok(srv)
+        })
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let conn = MockConn::many(vec![Some(io1), Some(io2)]);
+    let h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    let mut pool = Pool::new(h2);
+
+    let get = || {
+        http::Request::builder()
+            .method("GET")
+            .uri("https://example.com/")
+            .body(NoBody)
+            .unwrap()
+    };
+
+    let first = pool.call(get());
+    let mut pool2 = pool.clone();
+    let second = sent_rx
+        .map_err(|e| panic!("{:?}", e))
+        .and_then(move |_| pool2.call(get()));
+
+    let done = first
+        .join(second)
+        .map(|(rsp1, rsp2)| {
+            assert_eq!(rsp1.status(), http::StatusCode::OK);
+            assert_eq!(rsp2.status(), http::StatusCode::OK);
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv1.join(srv2)))
+        .unwrap();
+}
+
+#[test]
+fn request_timeout_resets_stream() {
+    use futures::Async;
+    use std::time::Duration;
+    use tower_h2::client::RequestTimeout;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    // The server never responds, so the client gives up on the stream.
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .recv_frame(frames::reset(1).cancel())
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+    h2.set_request_timeout(Some(Duration::from_secs(60)));
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            // The request's own timeout takes precedence over the default.
+            let mut request = http::Request::builder()
+                .method("GET")
+                .uri("https://example.com/")
+                .body(NoBody)
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(RequestTimeout::new(Duration::from_millis(10)));
+
+            let mut rsp = h2.call(request);
+            future::poll_fn(move || {
+                match rsp.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(ref e) if e.is_timeout() => {}
+                    res => panic!("unexpected result: {:?}", res),
+                }
+
+                // Polling again after the timeout fails the same way.
+                match rsp.poll() {
+                    Err(ref e) if e.is_timeout() => {}
+                    res => panic!("unexpected result: {:?}", res),
+                }
+
+                Ok(Async::Ready(()))
+            })
+        })
+        .map_err(|e: tower_h2::client::Error| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+}
+
+#[test]
+fn dropping_response_future_cancels_request() {
+    use futures::sync::oneshot;
+    use std::sync::atomic::Ordering;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+    let (sent_tx, sent_rx) = oneshot::channel::<()>();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+        )
+        .and_then(move |v| {
+            sent_tx.send(()).unwrap();
+            Ok(v)
+        })
+        .recv_frame(frames::reset(1).cancel())
+        .close();
+
+    let (body, dropped) = PendingBody::new();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(move |mut h2| {
+            let rsp = h2.call(http::Request::builder()
+                .method("POST")
+                .uri("https://example.com/")
+                .body(body)
+                .unwrap());
+
+            // Give up on the response once the request is in flight.
+            sent_rx
+                .map(move |_| drop(rsp))
+                .map_err(|e| panic!("{:?}", e))
+        });
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+
+    assert!(dropped.load(Ordering::SeqCst), "request body was not dropped");
+}
+
+#[test]
+fn dropping_response_body_cancels_request() {
+    use std::sync::atomic::Ordering;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+        )
+        .send_frame(frames::headers(1).response(200))
+        .send_frame(frames::data(1, "hello"))
+        .recv_frame(frames::reset(1).cancel())
+        .close();
+
+    let (body, dropped) = PendingBody::new();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(move |mut h2| {
+            h2.call(http::Request::builder()
+                .method("POST")
+                .uri("https://example.com/")
+                .body(body)
+                .unwrap())
+        })
+        .map(|rsp| {
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+            // Drop the response body before it has been received.
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+
+    assert!(dropped.load(Ordering::SeqCst), "request body was not dropped");
+}
+
+#[test]
+fn errors_are_categorized() {
+    use tower_h2::client::ErrorKind;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .recv_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos()
+        )
+        .send_frame(frames::reset(1).refused())
+        // Stream 3 was never processed.
+        .send_frame(frames::go_away(1))
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let get = || {
+        http::Request::builder()
+            .method("GET")
+            .uri("https://example.com/")
+            .body(NoBody)
+            .unwrap()
+    };
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(move |mut h2| {
+            let first = h2.call(get()).then(|res| {
+                let err = res.expect_err("first request should fail");
+                assert_eq!(err.kind(), ErrorKind::Reset(tower_h2::Reason::REFUSED_STREAM));
+                Ok::<_, ()>(())
+            });
+
+            let second = h2.call(get()).then(|res| {
+                let err = res.expect_err("second request should fail");
+                match err.kind() {
+                    ErrorKind::Closed { go_away: Some(go_away) } => {
+                        assert_eq!(go_away.last_stream_id(), 1.into());
+                    }
+                    kind => panic!("unexpected error kind: {:?}", kind),
+                }
+                Ok::<_, ()>(())
+            });
+
+            first.join(second)
+        });
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+}
+
+#[test]
+fn errors_are_categorized_by_origin() {
+    use tower_h2::client::ErrorKind;
+    use tower_h2::Reason;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        // The same reason, first on a stream and then on the connection.
+        .send_frame(frames::reset(1).protocol_error())
+        .idle_ms(10)
+        .send_frame(frames::go_away(3).reason(Reason::PROTOCOL_ERROR))
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let get = || {
+        http::Request::builder()
+            .method("GET")
+            .uri("https://example.com/")
+            .body(NoBody)
+            .unwrap()
+    };
+
+    let done = h2
+        .make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(move |mut h2| {
+            let first = h2.call(get()).then(|res| {
+                let err = res.expect_err("first request should fail");
+                assert_eq!(err.kind(), ErrorKind::Reset(Reason::PROTOCOL_ERROR));
+                Ok::<_, ()>(())
+            });
+
+            let second = h2.call(get()).then(|res| {
+                let err = res.expect_err("second request should fail");
+                match err.kind() {
+                    ErrorKind::Closed {
+                        go_away: Some(go_away),
+                    } => {
+                        assert_eq!(go_away.reason(), Reason::PROTOCOL_ERROR);
+                    }
+                    kind => panic!("unexpected error kind: {:?}", kind),
+                }
+                Ok::<_, ()>(())
+            });
+
+            first.join(second)
+        });
+
+    Runtime::new().unwrap().block_on(done.join(srv)).unwrap();
+}
+
+#[test]
+fn server_flow_control_violation_is_categorized() {
+    use tower_h2::client::ErrorKind;
+    use tower_h2::Reason;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    // Overflowing the client's send window is a connection error, which the
+    // client detects without receiving a GOAWAY.
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .send_frame(frames::window_update(0, 0x7FFF_FFFF))
+        .recv_frame(frames::go_away(0).reason(Reason::FLOW_CONTROL_ERROR))
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2
+        .make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            let request = http::Request::builder()
+                .method("GET")
+                .uri("https://example.com/")
+                .body(NoBody)
+                .unwrap();
+
+            // The request's stream and the connection report the same kind.
+            h2.call(request).then(move |res| {
+                let err = res.expect_err("request should fail");
+                assert_eq!(err.kind(), ErrorKind::FlowControl);
+
+                let err = h2.poll_ready().expect_err("connection should fail");
+                assert_eq!(err.kind(), ErrorKind::FlowControl);
+                Ok(())
+            })
+        });
+
+    Runtime::new().unwrap().block_on(done.join(srv)).unwrap();
+}
+
+#[derive(Debug)]
+struct UploadError;
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "upload failed")
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// A request body that fails immediately.
+struct FailingBody;
+
+impl tower_h2::Body for FailingBody {
+    type Data = <bytes::Bytes as bytes::IntoBuf>::Buf;
+    type Error = UploadError;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Err(UploadError)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Err(UploadError)
+    }
+}
+
+#[test]
+fn request_body_error_is_returned() {
+    use tower_h2::client::ErrorKind;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+        )
+        .recv_frame(frames::reset(1).internal_error())
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2.make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            h2.call(http::Request::builder()
+                .method("POST")
+                .uri("https://example.com/")
+                .body(FailingBody)
+                .unwrap())
+        })
+        .then(|res| {
+            let err = res.expect_err("request should fail");
+            assert_eq!(err.kind(), ErrorKind::Body);
+
+            let body_error = err.body_error().expect("body error");
+            assert_eq!(body_error.to_string(), "upload failed");
+            assert_eq!(body_error.reason(), tower_h2::Reason::INTERNAL_ERROR);
+            // `UploadError` is not known to be `Send + Sync`.
+            assert!(body_error.get_ref().is_none());
+            Ok(())
+        });
+
+    Runtime::new()
+        .unwrap()
+        .block_on(done.join(srv))
+        .unwrap();
+}
+
+/// A request body that fails immediately with an `io::Error`.
+struct IoFailingBody;
+
+impl tower_h2::Body for IoFailingBody {
+    type Data = <bytes::Bytes as bytes::IntoBuf>::Buf;
+    type Error = std::io::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "upload failed",
+        ))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(None.into())
+    }
+}
+
+#[test]
+fn request_body_error_keeps_original_error() {
+    use tower_h2::client::ErrorKind;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(frames::headers(1).request("POST", "https://example.com/"))
+        .recv_frame(frames::reset(1).internal_error())
+        .close();
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), TaskExecutor::current());
+
+    let done = h2
+        .make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            h2.call(
+                http::Request::builder()
+                    .method("POST")
+                    .uri("https://example.com/")
+                    .body(IoFailingBody)
+                    .unwrap(),
+            )
+        })
+        .then(|res| {
+            let err = res.expect_err("request should fail");
+            assert_eq!(err.kind(), ErrorKind::Body);
+
+            let body_error = err.body_error().expect("body error");
+            assert_eq!(body_error.to_string(), "upload failed");
+            let io = body_error
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<std::io::Error>())
+                .expect("io error");
+            assert_eq!(io.kind(), std::io::ErrorKind::BrokenPipe);
+            Ok(())
+        });
+
+    Runtime::new().unwrap().block_on(done.join(srv)).unwrap();
+}
+
+/// Counts the tasks spawned on the current thread's executor.
+#[derive(Clone)]
+struct CountingExecutor(std::rc::Rc<std::cell::Cell<usize>>);
+
+impl<F> future::Executor<F> for CountingExecutor
+where
+    F: Future<Item = (), Error = ()> + 'static,
+{
+    fn execute(&self, f: F) -> Result<(), future::ExecuteError<F>> {
+        self.0.set(self.0.get() + 1);
+        future::Executor::execute(&TaskExecutor::current(), f)
+    }
+}
+
+#[test]
+fn inline_flush_sends_buffered_body_without_spawning() {
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(frames::headers(1).request("GET", "https://example.com/"))
+        .recv_frame(frames::data(1, "hello world").eos())
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let spawned = std::rc::Rc::new(std::cell::Cell::new(0));
+    let executor = CountingExecutor(spawned.clone());
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), executor);
+    h2.set_inline_flush(true);
+
+    let done = h2
+        .make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            h2.call(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("https://example.com/")
+                    .body(SendBody::new("hello world"))
+                    .unwrap(),
+            )
+        })
+        .map(|rsp| {
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new().unwrap().block_on(done.join(srv)).unwrap();
+
+    // Only the connection's task was spawned.
+    assert_eq!(spawned.get(), 1);
+}
+
+/// A request body of two chunks, both ready.
+struct TwoChunks(usize);
+
+impl tower_h2::Body for TwoChunks {
+    type Data = <bytes::Bytes as bytes::IntoBuf>::Buf;
+    type Error = tower_h2::Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.0 == 2
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        use bytes::IntoBuf;
+
+        if self.0 == 2 {
+            return Ok(None.into());
+        }
+        self.0 += 1;
+        Ok(Some(bytes::Bytes::from_static(b"hello").into_buf()).into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(None.into())
+    }
+}
+
+#[test]
+fn inline_flush_spawns_task_for_larger_body() {
+    let _ = ::env_logger::try_init();
+
+    let (io, srv) = mock::new();
+
+    let srv = srv
+        .assert_client_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(frames::headers(1).request("GET", "https://example.com/"))
+        .recv_frame(frames::data(1, "hello"))
+        .recv_frame(frames::data(1, "hello").eos())
+        .send_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let spawned = std::rc::Rc::new(std::cell::Cell::new(0));
+    let executor = CountingExecutor(spawned.clone());
+
+    let conn = MockConn::new(io);
+    let mut h2 = Connect::new(conn, Default::default(), executor);
+    h2.set_inline_flush(true);
+
+    let done = h2
+        .make_service(())
+        .map_err(|e| panic!("connect err: {:?}", e))
+        .and_then(|mut h2| {
+            h2.call(
+                http::Request::builder()
+                    .method("GET")
+                    .uri("https://example.com/")
+                    .body(TwoChunks(0))
+                    .unwrap(),
+            )
+        })
+        .map(|rsp| {
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+        })
+        .map_err(|e| panic!("error: {:?}", e));
+
+    Runtime::new().unwrap().block_on(done.join(srv)).unwrap();
+
+    // The rest of the body was flushed by a spawned task.
+    assert_eq!(spawned.get(), 2);
+}
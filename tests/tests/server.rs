@@ -512,6 +512,27 @@ impl std::error::Error for Nesty {
     }
 }
 
+/// Sends one chunk, and then never finishes.
+struct Streaming(bool);
+
+impl Body for Streaming {
+    type Data = <Bytes as IntoBuf>::Buf;
+    type Error = tower_h2::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        if self.0 {
+            Ok(futures::Async::NotReady)
+        } else {
+            self.0 = true;
+            Ok(Some(Bytes::from_static(b"hello").into_buf()).into())
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(None.into())
+    }
+}
+
 struct ErrorBody(bool);
 
 impl Body for ErrorBody {
@@ -595,3 +616,1591 @@ fn service_error_sends_reset() {
     let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
     Runtime::new().unwrap().block_on(f).unwrap();
 }
+
+#[test]
+fn serve_with_settings() {
+    use tower_h2::server::{ServeOptions, ServerSettings};
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_custom_settings(
+            frames::settings()
+                .initial_window_size(1_000)
+                .max_concurrent_streams(10),
+        )
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            let response = http::Response::builder().status(200).body(NoBody).unwrap();
+
+            Ok::<_, tower_h2::Error>(response.into())
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let mut settings = ServerSettings::new();
+    settings.initial_window_size(1_000);
+    h2.set_settings(settings);
+
+    // Settings given to `serve_with` are merged with the server's.
+    let mut settings = ServerSettings::new();
+    settings.max_concurrent_streams(10);
+
+    let f = h2
+        .serve_with(io, ServeOptions::new().settings(settings))
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn shutdown_handle_drains_connection() {
+    use futures::sync::oneshot;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let (tx, rx) = oneshot::channel::<http::Response<NoBody>>();
+    let rx = RefCell::new(Some(rx));
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(move |_request| {
+            rx.borrow_mut()
+                .take()
+                .expect("called more than once")
+                .map_err(|_| tower_h2::Error::from(tower_h2::Reason::INTERNAL_ERROR))
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let conn = h2.serve(io);
+    let handle = conn.shutdown_handle();
+
+    let drained = Rc::new(RefCell::new(None));
+    let drained2 = drained.clone();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .idle_ms(10)
+        .and_then(move |v| {
+            *drained2.borrow_mut() = Some(handle.shutdown());
+            Ok(v)
+        })
+        .recv_frame(frames::go_away(2147483647))
+        .recv_frame(frames::ping(frame::Ping::SHUTDOWN))
+        .send_frame(frames::ping(frame::Ping::SHUTDOWN).pong())
+        .recv_frame(frames::go_away(1))
+        .and_then(move |v| {
+            let rsp = http::Response::builder().status(200).body(NoBody).unwrap();
+            tx.send(rsp).unwrap();
+            Ok(v)
+        })
+        .recv_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let f = conn.map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    // The connection and its response task have both completed.
+    let drained = drained.borrow_mut().take().expect("shutdown was not started");
+    assert_eq!(drained.wait(), Ok(()));
+}
+
+#[test]
+fn drain_timeout_forcibly_closes_connection() {
+    use std::time::Duration;
+    use tower_h2::server::Error;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            // Never respond, so the connection can't finish draining.
+            futures::future::empty::<http::Response<NoBody>, tower_h2::Error>()
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_drain_timeout(Some(Duration::from_millis(10)));
+
+    let conn = h2.serve(io);
+    let handle = conn.shutdown_handle();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .idle_ms(10)
+        .and_then(move |v| {
+            handle.shutdown();
+            Ok(v)
+        })
+        .recv_frame(frames::go_away(2147483647))
+        .recv_frame(frames::ping(frame::Ping::SHUTDOWN))
+        .send_frame(frames::ping(frame::Ping::SHUTDOWN).pong())
+        .recv_frame(frames::go_away(1))
+        // Once the drain timeout elapses, the in-flight stream is reset
+        // before the connection is closed.
+        .recv_frame(frames::reset(1).cancel())
+        .recv_frame(frames::go_away(1).reason(frame::Reason::CANCEL))
+        .close();
+
+    let conn = conn.then(|res| match res {
+        Err(Error::DrainTimeout) => Ok::<_, ()>(()),
+        res => panic!("expected drain timeout; got {:?}", res),
+    });
+
+    Runtime::new().unwrap().block_on(conn.join(client)).unwrap();
+}
+
+#[test]
+fn idle_timeout_closes_connection() {
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+    use tokio_timer::clock::Clock;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+    let now = MockNow::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            let response = http::Response::builder().status(200).body(NoBody).unwrap();
+
+            Ok::<_, tower_h2::Error>(response.into())
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_idle_timeout(Some(Duration::from_secs(10)));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                now.advance(Duration::from_secs(11));
+                Ok(v)
+            }
+        })
+        .recv_frame(frames::go_away(1))
+        .close();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+
+    let mut rt = current_thread::Builder::new()
+        .clock(Clock::new_with_now(now))
+        .build()
+        .unwrap();
+    rt.block_on(f).unwrap();
+}
+
+#[test]
+fn keepalive_timeout_closes_connection() {
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+    use tokio_timer::clock::Clock;
+    use tower_h2::server::Error;
+    use tower_h2::KeepAlive;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+    let now = MockNow::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            let response = http::Response::builder().status(200).body(NoBody).unwrap();
+
+            Ok::<_, tower_h2::Error>(response.into())
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_keepalive(Some(KeepAlive::new(
+        Duration::from_secs(10),
+        Duration::from_secs(5),
+    )));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                now.advance(Duration::from_secs(11));
+                Ok(v)
+            }
+        })
+        .recv_frame(frames::ping(frame::Ping::USER))
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                // Never acknowledge the PING.
+                now.advance(Duration::from_secs(6));
+                Ok(v)
+            }
+        })
+        .recv_frame(frames::go_away(0).reason(frame::Reason::CANCEL))
+        .close();
+
+    let conn = h2.serve(io).then(|res| match res {
+        Err(Error::KeepAliveTimeout) => Ok::<_, ()>(()),
+        res => panic!("expected keepalive timeout; got {:?}", res),
+    });
+
+    let mut rt = current_thread::Builder::new()
+        .clock(Clock::new_with_now(now))
+        .build()
+        .unwrap();
+    rt.block_on(conn.join(client)).unwrap();
+}
+
+#[test]
+fn keepalive_timeout_drops_connection_client_stopped_reading() {
+    use futures::sync::oneshot;
+    use std::time::Duration;
+    use tower_h2::server::Error;
+    use tower_h2::KeepAlive;
+
+    let _ = ::env_logger::try_init();
+
+    // Only the handshake fits in the connection's write buffer, so the PING
+    // and the GOAWAY can never be written once the client stops reading.
+    let (io, client) = mock::new_with_write_capacity(40);
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            let response = http::Response::builder().status(200).body(NoBody).unwrap();
+
+            Ok::<_, tower_h2::Error>(response.into())
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_keepalive(Some(KeepAlive::new(
+        Duration::from_millis(10),
+        Duration::from_millis(10),
+    )));
+
+    let (done_tx, done_rx) = oneshot::channel();
+
+    let conn = h2.serve(io).then(move |res| {
+        match res {
+            Err(Error::KeepAliveTimeout) => {}
+            res => panic!("expected keepalive timeout; got {:?}", res),
+        }
+        let _ = done_tx.send(());
+        Ok::<_, ()>(())
+    });
+
+    // Keep the client open, without reading from it, until the connection
+    // has been dropped.
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .and_then(move |client| done_rx.map(move |_| drop(client)).map_err(|_| panic!()));
+
+    Runtime::new().unwrap().block_on(conn.join(client)).unwrap();
+}
+
+#[test]
+fn keepalive_does_not_prevent_idle_timeout() {
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+    use tokio_timer::clock::Clock;
+    use tower_h2::KeepAlive;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+    let now = MockNow::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            let response = http::Response::builder().status(200).body(NoBody).unwrap();
+
+            Ok::<_, tower_h2::Error>(response.into())
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_idle_timeout(Some(Duration::from_secs(10)));
+    h2.set_keepalive(Some(KeepAlive::new(
+        Duration::from_secs(8),
+        Duration::from_secs(1),
+    )));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                now.advance(Duration::from_millis(8_500));
+                Ok(v)
+            }
+        })
+        .recv_frame(frames::ping(frame::Ping::USER))
+        .send_frame(frames::ping(frame::Ping::USER).pong())
+        // Once our own PING is acknowledged, the server has read the PONG.
+        .send_frame(frames::ping([1, 2, 3, 4, 5, 6, 7, 8]))
+        .recv_frame(frames::ping([1, 2, 3, 4, 5, 6, 7, 8]).pong())
+        // PINGs are not activity, so the connection has been idle since the
+        // response was sent.
+        .and_then({
+            let now = now.clone();
+            move |v| {
+                now.advance(Duration::from_secs(2));
+                Ok(v)
+            }
+        })
+        .recv_frame(frames::go_away(1))
+        .close();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+
+    let mut rt = current_thread::Builder::new()
+        .clock(Clock::new_with_now(now))
+        .build()
+        .unwrap();
+    rt.block_on(f).unwrap();
+}
+
+#[test]
+fn grpc_timeout_deadline_resets_stream() {
+    use tower_h2::server::Deadline;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            // Never respond, so the deadline passes.
+            futures::future::empty::<http::Response<NoBody>, tower_h2::Error>()
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_deadline(Some(Deadline::grpc_timeout()));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+                .field("grpc-timeout", "10m")
+                .eos(),
+        )
+        .recv_frame(frames::reset(1).cancel())
+        .close();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn grpc_timeout_deadline_allows_largest_timeout() {
+    use tower_h2::server::Deadline;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            let rsp = http::Response::builder().status(200).body(NoBody).unwrap();
+            futures::future::ok::<_, tower_h2::Error>(rsp)
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_deadline(Some(Deadline::grpc_timeout()));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+                .field("grpc-timeout", "99999999H")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn fixed_deadline_resets_stream() {
+    use std::time::Duration;
+    use tower_h2::server::Deadline;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            // Never respond, so the deadline passes.
+            futures::future::empty::<http::Response<NoBody>, tower_h2::Error>()
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_deadline(Some(Deadline::fixed(Duration::from_millis(10))));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::reset(1).cancel())
+        .close();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn deadline_sends_timeout_response() {
+    use std::time::Duration;
+    use tower_h2::server::Deadline;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_request| {
+            // Never respond, so the deadline passes.
+            futures::future::empty::<http::Response<NoBody>, tower_h2::Error>()
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    let mut deadline = Deadline::fixed(Duration::from_millis(10));
+    deadline.set_timeout_response(|| {
+        http::Response::builder()
+            .status(504)
+            .header("grpc-status", "4")
+            .body(())
+            .unwrap()
+    });
+    h2.set_deadline(Some(deadline));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(
+            frames::headers(1)
+                .response(504)
+                .field("grpc-status", "4")
+                .eos(),
+        )
+        .close();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn parse_grpc_timeout_units() {
+    use std::time::Duration;
+    use tower_h2::server::parse_grpc_timeout;
+
+    let parse = |s: &'static str| parse_grpc_timeout(&http::HeaderValue::from_static(s));
+
+    assert_eq!(parse("3H"), Some(Duration::from_secs(3 * 60 * 60)));
+    assert_eq!(parse("3M"), Some(Duration::from_secs(3 * 60)));
+    assert_eq!(parse("3S"), Some(Duration::from_secs(3)));
+    assert_eq!(parse("3m"), Some(Duration::from_millis(3)));
+    assert_eq!(parse("3u"), Some(Duration::from_micros(3)));
+    assert_eq!(parse("3n"), Some(Duration::from_nanos(3)));
+    assert_eq!(parse("0S"), Some(Duration::from_secs(0)));
+    assert_eq!(parse("00000010m"), Some(Duration::from_millis(10)));
+    assert_eq!(
+        parse("99999999H"),
+        Some(Duration::from_secs(99_999_999 * 60 * 60))
+    );
+}
+
+#[test]
+fn parse_grpc_timeout_rejects_malformed() {
+    use tower_h2::server::parse_grpc_timeout;
+
+    let parse = |s: &'static str| parse_grpc_timeout(&http::HeaderValue::from_static(s));
+
+    for value in &[
+        "",
+        "S",
+        "10",
+        "10x",
+        "10s",
+        "10h",
+        "-1S",
+        "+1S",
+        "1.5S",
+        " 1S",
+        "1 S",
+        "1SS",
+        // More than 8 digits.
+        "123456789S",
+        "184467440737095516160H",
+    ] {
+        assert_eq!(parse(value), None, "value={:?}", value);
+    }
+}
+
+#[test]
+fn cancel_token_resolves_on_reset() {
+    use futures::sync::mpsc;
+    use futures::Stream;
+    use tower_h2::server::CancelToken;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+    let (tx, rx) = mpsc::unbounded();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(move |request| {
+            // Watch for cancellation from outside of the response future,
+            // which is dropped when the stream is reset.
+            let token = request
+                .extensions()
+                .get::<CancelToken>()
+                .expect("cancel token")
+                .clone();
+            let tx = tx.clone();
+            tokio_current_thread::spawn(token.map(move |reason| {
+                tx.unbounded_send(reason).unwrap();
+            }));
+
+            futures::future::empty::<http::Response<NoBody>, tower_h2::Error>()
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .send_frame(frames::reset(1).refused())
+        .idle_ms(10)
+        .close();
+
+    let reason = rx
+        .into_future()
+        .map(|(reason, _)| assert_eq!(reason, Some(frame::Reason::REFUSED_STREAM)))
+        .map_err(|_| panic!("token dropped"));
+
+    let f = h2
+        .serve(io)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client)
+        .join(reason);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn cancel_token_resolves_on_reset_during_body() {
+    use futures::sync::mpsc;
+    use futures::Stream;
+    use tower_h2::server::CancelToken;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+    let (tx, rx) = mpsc::unbounded();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(move |request| {
+            let token = request
+                .extensions()
+                .get::<CancelToken>()
+                .expect("cancel token")
+                .clone();
+            let tx = tx.clone();
+            tokio_current_thread::spawn(token.map(move |reason| {
+                tx.unbounded_send(reason).unwrap();
+            }));
+
+            let response = http::Response::builder()
+                .status(200)
+                .body(Streaming(false))
+                .unwrap();
+            Ok::<_, tower_h2::Error>(response)
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200))
+        .recv_frame(frames::data(1, &b"hello"[..]))
+        .send_frame(frames::reset(1).refused())
+        .idle_ms(10)
+        .close();
+
+    let reason = rx
+        .into_future()
+        .map(|(reason, _)| assert_eq!(reason, Some(frame::Reason::REFUSED_STREAM)))
+        .map_err(|_| panic!("token dropped"));
+
+    let f = h2
+        .serve(io)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client)
+        .join(reason);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn error_responder_maps_service_error() {
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/mapped")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(500).eos())
+        .send_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/refused")
+                .eos(),
+        )
+        .recv_frame(frames::reset(3).refused())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|req| -> Result<http::Response<NoBody>, Box<dyn std::error::Error>> {
+            match req.uri().path() {
+                "/refused" => Err(tower_h2::Error::from(tower_h2::Reason::REFUSED_STREAM).into()),
+                _ => Err("internal error".into()),
+            }
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_error_responder(|err: &(dyn std::error::Error + 'static)| {
+        // Leave HTTP/2 errors to reset the stream.
+        if err.downcast_ref::<tower_h2::Error>().is_some() {
+            return None;
+        }
+
+        let response = http::Response::builder().status(500).body(NoBody).unwrap();
+        Some(response)
+    });
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[derive(Debug)]
+struct Shed;
+
+impl std::fmt::Display for Shed {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "load shed")
+    }
+}
+
+impl std::error::Error for Shed {}
+
+impl tower_h2::ResetReason for Shed {
+    fn reset_reason(&self) -> Option<tower_h2::Reason> {
+        Some(tower_h2::Reason::REFUSED_STREAM)
+    }
+}
+
+#[test]
+fn reset_reasons_classify_service_errors() {
+    use std::io;
+    use tower_h2::ResetReasons;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/shed")
+                .eos(),
+        )
+        .recv_frame(frames::reset(1).refused())
+        .send_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/timeout")
+                .eos(),
+        )
+        .recv_frame(frames::reset(3).cancel())
+        .send_frame(
+            frames::headers(5)
+                .request("GET", "https://example.com/other")
+                .eos(),
+        )
+        .recv_frame(frames::reset(5).internal_error())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|req| -> Result<http::Response<NoBody>, Box<dyn std::error::Error>> {
+            match req.uri().path() {
+                "/shed" => Err(Nesty(Shed.into()).into()),
+                "/timeout" => Err(io::Error::new(io::ErrorKind::TimedOut, "backend timed out").into()),
+                _ => Err(io::Error::new(io::ErrorKind::Other, "backend failed").into()),
+            }
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let mut reasons = ResetReasons::new();
+    reasons
+        .register::<Shed>()
+        .io_error(io::ErrorKind::TimedOut, tower_h2::Reason::CANCEL);
+    h2.set_reset_reasons(reasons);
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn on_flush_reports_body_error() {
+    use std::sync::{Arc, Mutex};
+    use tower_h2::server::{FlushOutcome, OnFlush};
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/body")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200))
+        .recv_frame(frames::reset(1).refused())
+        .close();
+
+    let outcome = Arc::new(Mutex::new(None));
+    let outcome2 = outcome.clone();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(move |_req| {
+            let outcome = outcome2.clone();
+            let mut response = http::Response::new(ErrorBody(false));
+            response.extensions_mut().insert(OnFlush::new(move |o| {
+                *outcome.lock().unwrap() = Some(o);
+            }));
+            Ok::<_, tower_h2::Error>(response)
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    match outcome.lock().unwrap().take() {
+        Some(FlushOutcome::Body(err)) => {
+            assert_eq!(err.reason(), tower_h2::Reason::REFUSED_STREAM);
+            assert!(err.to_string().starts_with("nesty: "), "{}", err);
+        }
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
+
+#[test]
+fn on_flush_reports_complete() {
+    use std::sync::{Arc, Mutex};
+    use tower_h2::server::{FlushOutcome, OnFlush};
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200))
+        .recv_frame(frames::data(1, &b"hello"[..]).eos())
+        .close();
+
+    let outcome = Arc::new(Mutex::new(None));
+    let outcome2 = outcome.clone();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(move |_req| {
+            let outcome = outcome2.clone();
+            let mut response = http::Response::new(SendBody::new("hello"));
+            response.extensions_mut().insert(OnFlush::new(move |o| {
+                *outcome.lock().unwrap() = Some(o);
+            }));
+            Ok::<_, tower_h2::Error>(response)
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    match outcome.lock().unwrap().take() {
+        Some(FlushOutcome::Complete) => {}
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
+
+#[test]
+fn on_flush_reports_client_reset() {
+    use std::sync::{Arc, Mutex};
+    use tower_h2::server::{FlushOutcome, OnFlush};
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200))
+        .recv_frame(frames::data(1, &b"hello"[..]))
+        .send_frame(frames::reset(1).refused())
+        .idle_ms(10)
+        .close();
+
+    let outcome = Arc::new(Mutex::new(None));
+    let outcome2 = outcome.clone();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(move |_req| {
+            let outcome = outcome2.clone();
+            let mut response = http::Response::new(Streaming(false));
+            response.extensions_mut().insert(OnFlush::new(move |o| {
+                *outcome.lock().unwrap() = Some(o);
+            }));
+            Ok::<_, tower_h2::Error>(response)
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    match outcome.lock().unwrap().take() {
+        Some(FlushOutcome::Reset(reason)) => {
+            assert_eq!(reason, tower_h2::Reason::REFUSED_STREAM);
+        }
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
+
+#[test]
+fn in_flight_limit_refuses_streams() {
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200))
+        .send_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::reset(3).refused())
+        .send_frame(frames::reset(1).cancel())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_req| {
+            let (body, _) = PendingBody::new();
+            Ok::<_, tower_h2::Error>(http::Response::new(body))
+        }),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_max_in_flight_per_connection(Some(1));
+    let load_shed = h2.load_shed();
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    assert_eq!(load_shed.connection_limited(), 1);
+    assert_eq!(load_shed.server_limited(), 0);
+    assert_eq!(load_shed.in_flight(), 0);
+}
+
+/// Makes services that are never ready if they were not the first one made.
+#[derive(Clone)]
+struct MakeStalled(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+struct Stalled(bool);
+
+impl tower_service::Service<()> for MakeStalled {
+    type Response = Stalled;
+    type Error = ();
+    type Future = futures::future::FutureResult<Stalled, ()>;
+
+    fn poll_ready(&mut self) -> Poll<(), ()> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        let made = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        futures::future::ok(Stalled(made != 0))
+    }
+}
+
+impl tower_service::Service<Req> for Stalled {
+    type Response = http::Response<NoBody>;
+    type Error = tower_h2::Error;
+    type Future = futures::future::Empty<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.0 {
+            Ok(futures::Async::NotReady)
+        } else {
+            Ok(().into())
+        }
+    }
+
+    fn call(&mut self, _: Req) -> Self::Future {
+        // Never respond, so the request stays in flight.
+        futures::future::empty()
+    }
+}
+
+#[test]
+fn server_in_flight_limit_refuses_streams_across_connections() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    let _ = ::env_logger::try_init();
+
+    let (io1, client1) = mock::new();
+    let (io2, client2) = mock::new();
+
+    // The first connection's request holds the server's only permit until
+    // the second connection's request has been refused.
+    let client1 = client1
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .idle_ms(100)
+        .send_frame(frames::reset(1).cancel())
+        .close();
+
+    // The second connection's service is never ready, so its request is
+    // refused while the connection waits for the service.
+    let client2 = client2
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .idle_ms(20)
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::reset(1).refused())
+        .close();
+
+    let mut h2 = Server::new(
+        MakeStalled(Arc::new(AtomicUsize::new(0))),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_max_in_flight(Some(1));
+    let load_shed = h2.load_shed();
+
+    let f = h2
+        .serve(io1)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client1)
+        .join(
+            h2.serve(io2)
+                .map_err(|e| panic!("err={:?}", e))
+                .join(client2),
+        );
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    assert_eq!(load_shed.server_limited(), 1);
+    assert_eq!(load_shed.connection_limited(), 0);
+    assert_eq!(load_shed.in_flight(), 0);
+}
+
+/// Makes services that fail `poll_ready` if they were the first one made.
+#[derive(Clone)]
+struct MakeFlaky(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+struct Flaky(bool);
+
+impl tower_service::Service<()> for MakeFlaky {
+    type Response = Flaky;
+    type Error = ();
+    type Future = futures::future::FutureResult<Flaky, ()>;
+
+    fn poll_ready(&mut self) -> Poll<(), ()> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        let made = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        futures::future::ok(Flaky(made == 0))
+    }
+}
+
+impl tower_service::Service<Req> for Flaky {
+    type Response = http::Response<NoBody>;
+    type Error = tower_h2::Error;
+    type Future = futures::future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.0 {
+            Err(tower_h2::Reason::INTERNAL_ERROR.into())
+        } else {
+            Ok(().into())
+        }
+    }
+
+    fn call(&mut self, _: Req) -> Self::Future {
+        futures::future::ok(http::Response::new(NoBody))
+    }
+}
+
+#[test]
+fn recreates_failed_service() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let made = Arc::new(AtomicUsize::new(0));
+    let mut h2 = Server::new(
+        MakeFlaky(made.clone()),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_recreate_on_service_error(true);
+
+    let f = h2.serve(io).map_err(|e| panic!("err={:?}", e)).join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    assert_eq!(made.load(Ordering::SeqCst), 2);
+}
+
+/// Makes services that always fail `poll_ready`.
+#[derive(Clone)]
+struct MakeFailing(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+impl tower_service::Service<()> for MakeFailing {
+    type Response = Flaky;
+    type Error = ();
+    type Future = futures::future::FutureResult<Flaky, ()>;
+
+    fn poll_ready(&mut self) -> Poll<(), ()> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        futures::future::ok(Flaky(true))
+    }
+}
+
+#[test]
+fn gives_up_recreating_always_failing_service() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower_h2::server::Error;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(frames::go_away(2147483647))
+        .recv_frame(frames::ping(frame::Ping::SHUTDOWN))
+        .send_frame(frames::ping(frame::Ping::SHUTDOWN).pong())
+        .recv_frame(frames::go_away(0))
+        .close();
+
+    let made = Arc::new(AtomicUsize::new(0));
+    let mut h2 = Server::new(
+        MakeFailing(made.clone()),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_recreate_on_service_error(true);
+
+    let conn = h2.serve(io).then(|res| match res {
+        Err(Error::Service(_)) => Ok::<_, ()>(()),
+        res => panic!("expected service error; got {:?}", res),
+    });
+    Runtime::new().unwrap().block_on(conn.join(client)).unwrap();
+
+    // The first service, and the three that replaced it.
+    assert_eq!(made.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn max_recreates_limits_replacement_services() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower_h2::server::Error;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .recv_frame(frames::go_away(2147483647))
+        .recv_frame(frames::ping(frame::Ping::SHUTDOWN))
+        .send_frame(frames::ping(frame::Ping::SHUTDOWN).pong())
+        .recv_frame(frames::go_away(0))
+        .close();
+
+    let made = Arc::new(AtomicUsize::new(0));
+    let mut h2 = Server::new(
+        MakeFailing(made.clone()),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_recreate_on_service_error(true);
+    h2.set_max_recreates(1);
+
+    let conn = h2.serve(io).then(|res| match res {
+        Err(Error::Service(_)) => Ok::<_, ()>(()),
+        res => panic!("expected service error; got {:?}", res),
+    });
+    Runtime::new().unwrap().block_on(conn.join(client)).unwrap();
+
+    // The first service, and the one that replaced it.
+    assert_eq!(made.load(Ordering::SeqCst), 2);
+}
+
+/// Makes services that check the `ConnectionInfo` of each request.
+struct MakeEcho;
+
+struct Echo(tower_h2::server::ConnectionInfo);
+
+impl tower_service::Service<tower_h2::server::ConnectionInfo> for MakeEcho {
+    type Response = Echo;
+    type Error = ();
+    type Future = futures::future::FutureResult<Echo, ()>;
+
+    fn poll_ready(&mut self) -> Poll<(), ()> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, info: tower_h2::server::ConnectionInfo) -> Self::Future {
+        futures::future::ok(Echo(info))
+    }
+}
+
+impl tower_service::Service<Req> for Echo {
+    type Response = http::Response<NoBody>;
+    type Error = tower_h2::Error;
+    type Future = futures::future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let info = req
+            .extensions()
+            .get::<std::sync::Arc<tower_h2::server::ConnectionInfo>>()
+            .expect("request has connection info");
+        assert_eq!(**info, self.0);
+
+        let status = if info.alpn_protocol() == Some(&b"h2"[..]) {
+            200
+        } else {
+            500
+        };
+        futures::future::ok(
+            http::Response::builder()
+                .status(status)
+                .body(NoBody)
+                .unwrap(),
+        )
+    }
+}
+
+#[test]
+fn serve_with_connection_info() {
+    use tower_h2::server::{ConnectionInfo, ServeOptions};
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let mut info = ConnectionInfo::new();
+    info.set_peer_addr("127.0.0.1:4321".parse().unwrap())
+        .set_alpn_protocol(b"h2".to_vec());
+
+    let mut h2 = Server::new(MakeEcho, Default::default(), TaskExecutor::current());
+    h2.set_insert_target(true);
+
+    let f = h2
+        .serve_with(io, ServeOptions::new().target(info))
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+/// Rejects requests for private paths.
+struct RejectPrivate;
+
+impl tower_h2::server::Admit<NoBody> for RejectPrivate {
+    type Future = futures::future::FutureResult<Req, tower_h2::server::Reject<NoBody>>;
+
+    fn admit(&mut self, req: Req) -> Self::Future {
+        use tower_h2::server::Reject;
+
+        match req.uri().path() {
+            "/private" => {
+                let rsp = http::Response::builder()
+                    .status(403)
+                    .body(NoBody)
+                    .unwrap();
+                futures::future::err(Reject::Respond(rsp))
+            }
+            "/reset" => futures::future::err(Reject::Reset(tower_h2::Reason::REFUSED_STREAM)),
+            _ => futures::future::ok(req),
+        }
+    }
+}
+
+#[test]
+fn admit_rejects_requests() {
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/private")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(403).eos())
+        .send_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/reset")
+                .eos(),
+        )
+        .recv_frame(frames::reset(3).refused())
+        .send_frame(
+            frames::headers(5)
+                .request("GET", "https://example.com/public")
+                .eos(),
+        )
+        .recv_frame(frames::headers(5).response(200).eos())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_req| Ok::<_, tower_h2::Error>(http::Response::new(NoBody))),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let f = h2
+        .serve_modified(io, RejectPrivate)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+/// Admits requests after a short delay, except for `/hang`, which is never
+/// admitted.
+struct AdmitLater;
+
+impl tower_h2::server::Admit<NoBody> for AdmitLater {
+    type Future = Box<dyn Future<Item = Req, Error = tower_h2::server::Reject<NoBody>>>;
+
+    fn admit(&mut self, req: Req) -> Self::Future {
+        use std::time::{Duration, Instant};
+        use tokio::timer::Delay;
+
+        if req.uri().path() == "/hang" {
+            return Box::new(futures::future::empty());
+        }
+
+        let delay = Delay::new(Instant::now() + Duration::from_millis(10));
+        Box::new(
+            delay
+                .map_err(|e| panic!("timer error: {:?}", e))
+                .map(move |_| req),
+        )
+    }
+}
+
+#[test]
+fn deadline_includes_time_waiting_for_admission() {
+    use tower_h2::server::Deadline;
+
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_req| Ok::<_, tower_h2::Error>(http::Response::new(NoBody))),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_deadline(Some(Deadline::grpc_timeout()));
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("POST", "https://example.com/hang")
+                .field("grpc-timeout", "10m")
+                .eos(),
+        )
+        .recv_frame(frames::reset(1).cancel())
+        .close();
+
+    let f = h2
+        .serve_modified(io, AdmitLater)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn admit_future_resolves_later() {
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(1).response(200).eos())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_req| Ok::<_, tower_h2::Error>(http::Response::new(NoBody))),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+
+    let f = h2
+        .serve_modified(io, AdmitLater)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+}
+
+#[test]
+fn reset_releases_request_waiting_for_admission() {
+    let _ = ::env_logger::try_init();
+
+    let (io, client) = mock::new();
+
+    // Stream 1 is never admitted, so it only stops counting towards the
+    // in-flight limit once the client resets it.
+    let client = client
+        .assert_server_handshake()
+        .unwrap()
+        .recv_settings()
+        .send_frame(
+            frames::headers(1)
+                .request("GET", "https://example.com/hang")
+                .eos(),
+        )
+        .idle_ms(10)
+        .send_frame(frames::reset(1).cancel())
+        .idle_ms(10)
+        .send_frame(
+            frames::headers(3)
+                .request("GET", "https://example.com/")
+                .eos(),
+        )
+        .recv_frame(frames::headers(3).response(200).eos())
+        .close();
+
+    let mut h2 = Server::new(
+        SyncServiceFn::new(|_req| Ok::<_, tower_h2::Error>(http::Response::new(NoBody))),
+        Default::default(),
+        TaskExecutor::current(),
+    );
+    h2.set_max_in_flight_per_connection(Some(1));
+    let load_shed = h2.load_shed();
+
+    let f = h2
+        .serve_modified(io, AdmitLater)
+        .map_err(|e| panic!("err={:?}", e))
+        .join(client);
+    Runtime::new().unwrap().block_on(f).unwrap();
+
+    assert_eq!(load_shed.connection_limited(), 0);
+}
@@ -5,14 +5,20 @@ pub extern crate h2_support;
 pub extern crate http;
 pub extern crate tokio;
 pub extern crate tokio_current_thread;
+pub extern crate tokio_timer;
 pub extern crate tower;
 pub extern crate tower_h2;
 pub extern crate tower_service;
 
 use bytes::{Buf, Bytes, IntoBuf};
 use futures::{Async, Future, Poll};
+use tokio_timer::clock::Now;
 use tower_h2::{Body, RecvBody};
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 // We can't import `try_ready` here because this module isn't at the crate
 // root, so we'll redefine it instead.
 #[macro_export]
@@ -58,6 +64,39 @@ impl Body for SendBody {
     }
 }
 
+/// A body that never produces any data, and records when it is dropped.
+pub struct PendingBody(Arc<AtomicBool>);
+
+impl PendingBody {
+    pub fn new() -> (Self, Arc<AtomicBool>) {
+        let dropped = Arc::new(AtomicBool::new(false));
+        (PendingBody(dropped.clone()), dropped)
+    }
+}
+
+impl Body for PendingBody {
+    type Data = <Bytes as IntoBuf>::Buf;
+    type Error = self::h2::Error;
+
+    fn is_end_stream(&self) -> bool {
+        false
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Ok(Async::NotReady)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(Async::NotReady)
+    }
+}
+
+impl Drop for PendingBody {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
 pub fn read_recv_body(body: RecvBody) -> ReadRecvBody {
     ReadRecvBody { body, bytes: None }
 }
@@ -84,3 +123,23 @@ impl Future for ReadRecvBody {
         }
     }
 }
+
+/// A clock that only moves forward when it is explicitly advanced.
+#[derive(Clone)]
+pub struct MockNow(Arc<Mutex<Instant>>);
+
+impl MockNow {
+    pub fn new() -> Self {
+        MockNow(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Now for MockNow {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}